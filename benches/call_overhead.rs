@@ -0,0 +1,67 @@
+//! Measures how calls find their function in a populated global env, the old
+//! way and the new.
+//!
+//! Runs with `cargo bench`. Calls used to clone every variable and scan the
+//! copy for the name, as `old_lookup` still does here; they now make one hashed
+//! lookup through `Env::get_function`.
+
+extern crate rlisp;
+
+use rlisp::{Env, Function, Interpreter, Number, Object};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+const GLOBALS: usize = 500;
+const LOOKUPS: usize = 2000;
+const RUNS: usize = 5;
+
+// `match_first_function(name, env.variables())` as dispatch called it.
+fn old_lookup(name: &str, vars: HashMap<String, Object>) -> Option<Function> {
+    for (var, object) in vars.iter() {
+        if var == name {
+            if let Object::Function(ref function) = *object {
+                return Some(function.clone());
+            }
+        }
+    }
+    None
+}
+
+fn best_of<F: FnMut()>(mut run: F) -> Duration {
+    let mut best = None;
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        run();
+        let elapsed = start.elapsed();
+        if best.map_or(true, |best| elapsed < best) {
+            best = Some(elapsed);
+        }
+    }
+    best.unwrap()
+}
+
+fn main() {
+    let mut interpreter = Interpreter::new();
+    for i in 0..GLOBALS {
+        interpreter.define(&format!("global{}", i), Object::Number(Number::Int(i as i64)));
+    }
+    interpreter.eval_str("(define (countdown n) (cond [(= n 0) 0] [else (countdown (- n 1))]))").unwrap();
+    let env: &Env = interpreter.env();
+    for name in ["+", "countdown"].iter() {
+        let old = best_of(|| {
+            for _ in 0..LOOKUPS {
+                assert!(old_lookup(name, env.variables()).is_some());
+            }
+        });
+        let new = best_of(|| {
+            for _ in 0..LOOKUPS {
+                assert!(env.get_function(name).is_some());
+            }
+        });
+        println!("{:>10}: old {:?}, new {:?} per lookup ({} globals bound)",
+                 name,
+                 old / LOOKUPS as u32,
+                 new / LOOKUPS as u32,
+                 GLOBALS);
+    }
+}
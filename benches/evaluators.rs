@@ -0,0 +1,53 @@
+//! Compares the bytecode vm with the tree-walker on the same programs.
+//!
+//! Runs with `cargo bench`. Each program is defined once per evaluator and
+//! then called repeatedly, keeping the fastest run. They recurse hundreds of
+//! calls deep, which the main thread's stack has room for in a release build.
+
+extern crate rlisp;
+
+use rlisp::{Env, Evaluator, Interpreter};
+use std::time::{Duration, Instant};
+
+const RUNS: usize = 5;
+
+const PROGRAMS: [(&'static str, &'static str, &'static str); 3] = [
+    ("fib",
+     "(define (fib n) (cond [(= n 0) 0] [(= n 1) 1] [else (+ (fib (- n 1)) (fib (- n 2)))]))",
+     "(fib 20)"),
+    ("closures",
+     "(define (adder n) (lambda (x) (+ x n)))
+      (define (apply-n f n x) (cond [(= n 0) x] [else (apply-n f (- n 1) (f x))]))",
+     "(apply-n (adder 3) 500 0)"),
+    ("guard",
+     "(define (checked n) (guard (e [(error-object? e) 0]) (cond [(= n 0) (error \"zero\")] [else n])))
+      (define (sum-checked n) (cond [(= n 0) (checked 0)] [else (+ (checked n) (sum-checked (- n 1)))]))",
+     "(sum-checked 500)"),
+];
+
+fn time(evaluator: Evaluator, definitions: &str, call: &str) -> Duration {
+    let mut env = Env::new();
+    env.evaluator = evaluator;
+    env.limits.max_depth = Some(1000);
+    let mut interpreter = Interpreter::with_env(env);
+    interpreter.eval_str(definitions).expect("Failed to define the benchmark.");
+    let mut best = None;
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        interpreter.eval_str(call).expect("The benchmark failed.");
+        let elapsed = start.elapsed();
+        if best.map_or(true, |best| elapsed < best) {
+            best = Some(elapsed);
+        }
+    }
+    best.unwrap()
+}
+
+fn main() {
+    for &(name, definitions, call) in PROGRAMS.iter() {
+        let bytecode = time(Evaluator::Bytecode, definitions, call);
+        let tree_walk = time(Evaluator::TreeWalk, definitions, call);
+        let speedup = tree_walk.as_secs_f64() / bytecode.as_secs_f64();
+        println!("{:>10}: bytecode {:?}, tree-walk {:?} ({:.1}x)", name, bytecode, tree_walk, speedup);
+    }
+}
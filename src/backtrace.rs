@@ -0,0 +1,54 @@
+//! Printing the calls an uncaught error unwound through, with where each call
+//! was read from. Calls made by forms that were not read, such as those built
+//! for `eval`, are printed without a position.
+
+use rlisp::{CallSite, Condition, Object};
+use rlisp::data::Expr;
+use std::collections::HashMap;
+
+/// Prints the backtrace of `condition`, if it has one.
+pub fn print_backtrace(condition: &Condition) {
+    if condition.backtrace.is_empty() {
+        return;
+    }
+    println!("Backtrace, innermost call first:");
+    print_calls(&condition.backtrace, condition.omitted_calls);
+}
+
+/// Prints `calls`, innermost first; `omitted` more calls further out were left
+/// out.
+pub fn print_calls(calls: &[CallSite], omitted: usize) {
+    // recursion shows as one line per run of the same call
+    let mut lines: Vec<(String, usize)> = Vec::new();
+    for site in calls {
+        let line = match site.span {
+            Some(ref span) => format!("{} at {}", site.function, span.position()),
+            None => site.function.clone(),
+        };
+        match lines.last_mut() {
+            Some(&mut (ref last, ref mut repeats)) if *last == line => *repeats += 1,
+            _ => lines.push((line, 0)),
+        }
+    }
+    for (line, repeats) in lines {
+        if repeats > 0 {
+            println!("  {} ({} more times)", line, repeats);
+        } else {
+            println!("  {}", line);
+        }
+    }
+    if omitted > 0 {
+        println!("  ... and {} more", omitted);
+    }
+}
+
+/// `expr` with the params in `locals` replaced by their values, as the
+/// tree-walker runs a function's body.
+pub fn substitute(expr: &Expr, locals: &[(String, Object)]) -> Expr {
+    let names = locals.iter().map(|&(ref name, _)| Object::Symbol(name.clone())).collect::<Vec<_>>();
+    let mut var_mappings = HashMap::new();
+    for (name, &(_, ref value)) in names.iter().zip(locals.iter()) {
+        var_mappings.insert(name, value);
+    }
+    expr.replace_all(&var_mappings)
+}
@@ -0,0 +1,178 @@
+//! Repl commands, written `,name args...` in place of a lisp form.
+
+use rlisp::{Interpreter, Object};
+use rlisp::data::{Env, Inhibit, LispFn};
+use rlisp::eval::{SPECIAL_FORMS, special_form_doc};
+use repl::{clear_results, remember_error, remember_result, run_file};
+use std::time::Instant;
+use style;
+
+// name, args, description
+const COMMANDS: [(&'static str, &'static str, &'static str); 8] = [
+    ("help", "", "List the commands"),
+    ("env", "[prefix]", "List the bindings, or those starting with prefix"),
+    ("type", "expr", "Show the type of expr's value"),
+    ("time", "expr", "Evaluate expr and show how long it took"),
+    ("load", "file", "Evaluate the forms in file"),
+    ("reset", "", "Drop all definitions, starting again from a fresh env"),
+    ("doc", "name", "Show the documentation of a builtin or special form"),
+    ("print", "", "Turn printing of results off or on"),
+];
+
+/// State of the repl that commands can change.
+pub struct Settings {
+    pub print_results: bool,
+}
+
+impl Settings {
+    pub fn new() -> Settings {
+        Settings {
+            print_results: true,
+        }
+    }
+}
+
+pub fn is_command(input: &str) -> bool {
+    input.trim_start().starts_with(',')
+}
+
+pub fn run_command(input: &str, interpreter: &mut Interpreter, settings: &mut Settings) -> Inhibit {
+    let input = input.trim().trim_start_matches(',');
+    let (name, arg) = match input.find(char::is_whitespace) {
+        Some(i) => (&input[..i], input[i..].trim()),
+        None => (input, ""),
+    };
+    match name {
+        "help" => help(),
+        "env" => env(arg, interpreter.env()),
+        "type" => {
+            return eval_then(arg, interpreter, |result| {
+                match result {
+                    Some(object) => println!("{}", object.type_name()),
+                    None => println!("no value"),
+                }
+            })
+        }
+        "time" => {
+            let start = Instant::now();
+            let print_results = settings.print_results;
+            return eval_then(arg, interpreter, |result| {
+                let elapsed = start.elapsed();
+                if let (true, Some(object)) = (print_results, result) {
+                    println!("{:?}", object);
+                }
+                println!("Elapsed: {:?}", elapsed);
+            });
+        }
+        "load" if !arg.is_empty() => run_file(arg, interpreter),
+        "reset" => {
+            *interpreter.env_mut() = fresh_env(interpreter.env());
+            clear_results(interpreter.env_mut());
+            println!("Reset to a fresh env");
+        }
+        "doc" if !arg.is_empty() => doc(arg, interpreter.env()),
+        "print" => {
+            settings.print_results = !settings.print_results;
+            println!("Result printing is {}", if settings.print_results { "on" } else { "off" });
+        }
+        _ => {
+            match COMMANDS.iter().find(|command| command.0 == name) {
+                Some(&(name, args, _)) => println!("Usage: ,{} {}", name, args),
+                None => println!("Unknown command ,{}; try ,help", name),
+            }
+        }
+    }
+    Inhibit::Continue
+}
+
+fn help() {
+    for &(name, args, description) in COMMANDS.iter() {
+        println!("  ,{:<16} {}", format!("{} {}", name, args), description);
+    }
+    println!("Anything else is evaluated as lisp.");
+    println!("*1, *2 and *3 are the values of the last three inputs that had one, counting");
+    println!(",type and ,time; an input's value is that of its last form. *e is the last error.");
+}
+
+fn env(prefix: &str, env: &Env) {
+    let mut names = env.variables.keys().filter(|name| name.starts_with(prefix)).collect::<Vec<_>>();
+    names.sort();
+    let width = names.iter().map(|name| name.chars().count()).max().unwrap_or(0);
+    for name in names {
+        match env.variables[name] {
+            Object::Function(_) => println!("{:<width$}  function", name, width = width),
+            ref value => println!("{:<width$}  {} = {:?}", name, value.type_name(), value, width = width),
+        }
+    }
+}
+
+// Evaluates `input` and hands the value of the last form to `show`, unless
+// evaluation failed or exited. The value or error is remembered as repl input's
+// would be.
+fn eval_then<F>(input: &str, interpreter: &mut Interpreter, show: F) -> Inhibit
+    where F: FnOnce(Option<&Object>)
+{
+    if input.is_empty() {
+        println!("Usage: give an expression to evaluate");
+        return Inhibit::Continue;
+    }
+    match interpreter.eval_str(input) {
+        Ok(Some(Object::Exit(s))) => return Inhibit::Stop(s),
+        Ok(result) => {
+            show(result.as_ref());
+            if let Some(result) = result {
+                remember_result(result, interpreter.env_mut());
+            }
+        }
+        Err(e) => {
+            println!("Eval of input failed with error: {}", style::paint(style::ERROR, &e.to_string()));
+            remember_error(e, interpreter.env_mut());
+        }
+    }
+    Inhibit::Continue
+}
+
+fn doc(name: &str, env: &Env) {
+    if SPECIAL_FORMS.contains(&name) {
+        println!("{}: special form", name);
+        if let Some(doc) = special_form_doc(name) {
+            println!("{}", doc);
+        }
+        return;
+    }
+    match env.variables.get(name) {
+        Some(&Object::Function(ref function)) => {
+            match *function.procedure {
+                LispFn::Builtin(ref builtin) => {
+                    println!("{}: builtin taking {}", name, builtin.arity());
+                    println!("{}", builtin.doc().unwrap_or("No documentation."));
+                }
+                LispFn::UserDef(ref params, _, _) => {
+                    let params = params.iter()
+                        .map(|param| {
+                            match *param {
+                                Object::Symbol(ref param) => format!(" {}", param),
+                                ref param => format!(" {:?}", param),
+                            }
+                        })
+                        .collect::<String>();
+                    println!("({}{}): user function", name, params);
+                }
+                LispFn::Continuation(_) => println!("{}: continuation", name),
+            }
+        }
+        Some(value) => println!("{} has type {}, not function", name, value.type_name()),
+        None => println!("{} is not bound", name),
+    }
+}
+
+// A new env, keeping the settings but none of the bindings of `old`.
+fn fresh_env(old: &Env) -> Env {
+    let mut env = Env::new();
+    env.evaluator = old.evaluator;
+    env.limits = old.limits.clone();
+    env.library_path = old.library_path.clone();
+    env.warn_on_redefine = old.warn_on_redefine;
+    env.debugging = old.debugging.clone();
+    env
+}
@@ -0,0 +1,221 @@
+use data::*;
+use parser::MAX_NESTING;
+
+#[derive(Debug, Clone)]
+pub enum Op {
+    Const(Object),
+    Lambda(Function), // made in the namespace it is run in
+    LoadLocal(usize),
+    LoadGlobal(String),
+    LoadFunction(String),
+    Call(usize, Expr), // number of args above the function on the stack, and the call form for backtraces
+    Pop,
+    JumpUnless(usize, Expr), // target, case reported if not a boolean
+    Jump(usize),
+    // anything without a dedicated op: locals are substituted into the expr and
+    // it is handed to the tree-walker
+    Interpret(Expr),
+    Fail(Condition),
+}
+
+#[derive(Debug)]
+pub struct Chunk {
+    pub params: Vec<Object>,
+    pub code: Vec<Op>,
+}
+
+pub fn compile_expr(expr: &Expr) -> Chunk {
+    let mut compiler = Compiler {
+        params: &[],
+        code: Vec::new(),
+        depth: 0,
+    };
+    compiler.expr(expr);
+    Chunk {
+        params: Vec::new(),
+        code: compiler.code,
+    }
+}
+
+pub fn compile_function(params: &[Object], body: &[Expr]) -> Chunk {
+    let mut compiler = Compiler {
+        params: params,
+        code: Vec::new(),
+        depth: 0,
+    };
+    if body.is_empty() {
+        compiler.code.push(Op::Fail(Condition::new("syntax-error", "Function body is empty".to_string())));
+    } else {
+        compiler.body(body);
+    }
+    Chunk {
+        params: params.to_vec(),
+        code: compiler.code,
+    }
+}
+
+struct Compiler<'a> {
+    params: &'a [Object],
+    code: Vec<Op>,
+    // how many forms the expr being compiled is inside
+    depth: usize,
+}
+
+impl<'a> Compiler<'a> {
+    fn body(&mut self, exprs: &[Expr]) {
+        let (last, leading) = exprs.split_last().unwrap();
+        for expr in leading {
+            self.expr(expr);
+            self.code.push(Op::Pop);
+        }
+        self.expr(last);
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match *expr {
+            Expr::Expr(ref object @ Object::Symbol(_)) => {
+                match self.slot(object) {
+                    Some(slot) => self.code.push(Op::LoadLocal(slot)),
+                    None => {
+                        if let Object::Symbol(ref name) = *object {
+                            self.code.push(Op::LoadGlobal(name.clone()));
+                        }
+                    }
+                }
+            }
+            Expr::Expr(Object::ConditionalCase(_, _)) if self.mentions_params(expr) => {
+                self.code.push(Op::Interpret(expr.clone()));
+            }
+            Expr::Expr(ref object) => self.code.push(Op::Const(object.clone())),
+            Expr::Exprs(ref exprs, _) => self.call(expr, exprs),
+        }
+    }
+
+    // Forms nested deeper than source allows are left to the tree-walker, which
+    // stops them with resource-exhausted.
+    fn call(&mut self, expr: &Expr, exprs: &[Expr]) {
+        if self.depth == MAX_NESTING {
+            return self.code.push(Op::Interpret(expr.clone()));
+        }
+        self.depth += 1;
+        self.form(expr, exprs);
+        self.depth -= 1;
+    }
+
+    fn form(&mut self, expr: &Expr, exprs: &[Expr]) {
+        let (head, args) = match exprs.split_first() {
+            Some(split) => split,
+            None => return self.code.push(Op::Interpret(expr.clone())),
+        };
+        match *head {
+            Expr::Expr(ref object @ Object::Symbol(_)) if self.slot(object).is_none() => {
+                let name = match *object {
+                    Object::Symbol(ref name) => name.as_str(),
+                    _ => unreachable!(),
+                };
+                match name {
+                    "define" | "set!" | "guard" | "module" => return self.code.push(Op::Interpret(expr.clone())),
+                    "lambda" => return self.lambda(expr, args),
+                    "quote" => return self.quote(args),
+                    "cond" if self.cond(args) => return,
+                    _ => self.code.push(Op::LoadFunction(name.to_string())),
+                }
+            }
+            Expr::Expr(Object::Symbol(_)) | Expr::Exprs(..) | Expr::Expr(Object::Function(_)) => self.expr(head),
+            _ => return self.code.push(Op::Interpret(expr.clone())),
+        }
+        for arg in args {
+            self.expr(arg);
+        }
+        self.code.push(Op::Call(args.len(), expr.clone()));
+    }
+
+    fn quote(&mut self, args: &[Expr]) {
+        let op = match args.len() {
+            1 => Op::Const(args[0].to_object()),
+            _ => Op::Fail(Condition::new("syntax-error", "quote takes exactly one form".to_string())),
+        };
+        self.code.push(op);
+    }
+
+    // Lambdas that close over params are built at runtime by substitution, the
+    // same way the tree-walker does it; the rest become constants so their
+    // compiled bodies are shared between evaluations.
+    fn lambda(&mut self, expr: &Expr, args: &[Expr]) {
+        if let Some((&Expr::Exprs(ref fndef, _), body)) = args.split_first() {
+            if !self.mentions_params(expr) {
+                let op = match Function::from_exprs(fndef, body) {
+                    Ok(function) => Op::Lambda(function),
+                    Err(e) => Op::Fail(e),
+                };
+                return self.code.push(op);
+            }
+        }
+        self.code.push(Op::Interpret(expr.clone()));
+    }
+
+    // Compiles a well formed cond into jumps, returning false to leave anything
+    // else to the cond builtin and its error reporting.
+    fn cond(&mut self, args: &[Expr]) -> bool {
+        let well_formed = args.len() >= 2 && args.iter().all(|arg| {
+            if let Expr::Expr(Object::ConditionalCase(_, _)) = *arg {
+                true
+            } else {
+                false
+            }
+        }) && match args[args.len() - 1] {
+            Expr::Expr(Object::ConditionalCase(ref case, _)) => **case == Expr::Expr(Object::Symbol("else".to_string())),
+            _ => false,
+        };
+        if !well_formed {
+            return false;
+        }
+        let mut end_jumps = Vec::new();
+        for (i, arg) in args.iter().enumerate() {
+            if let Expr::Expr(Object::ConditionalCase(ref case, ref body)) = *arg {
+                if i == args.len() - 1 {
+                    self.body(body);
+                } else {
+                    self.expr(case);
+                    let unless = self.code.len();
+                    self.code.push(Op::JumpUnless(0, (**case).clone()));
+                    self.body(body);
+                    end_jumps.push(self.code.len());
+                    self.code.push(Op::Jump(0));
+                    let next = self.code.len();
+                    self.code[unless] = Op::JumpUnless(next, (**case).clone());
+                }
+            }
+        }
+        let end = self.code.len();
+        for jump in end_jumps {
+            self.code[jump] = Op::Jump(end);
+        }
+        true
+    }
+
+    fn slot(&self, object: &Object) -> Option<usize> {
+        self.params.iter().position(|param| param == object)
+    }
+
+    // Walks the expr with a stack of its own, since it may be nested deeper
+    // than the compiler goes.
+    fn mentions_params(&self, expr: &Expr) -> bool {
+        let mut pending = vec![expr];
+        while let Some(expr) = pending.pop() {
+            match *expr {
+                Expr::Expr(Object::ConditionalCase(ref case, ref body)) => {
+                    pending.push(case);
+                    pending.extend(body.iter());
+                }
+                Expr::Expr(ref object) => {
+                    if self.slot(object).is_some() {
+                        return true;
+                    }
+                }
+                Expr::Exprs(ref exprs, _) => pending.extend(exprs.iter()),
+            }
+        }
+        false
+    }
+}
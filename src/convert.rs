@@ -0,0 +1,365 @@
+use data::*;
+use port::Port;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// Conversion from a lisp object into a Rust value, used for builtin args.
+pub trait FromLisp: Sized {
+    /// Whether the arg may be left off; only trailing args can be.
+    const OPTIONAL: bool = false;
+    fn type_name() -> String;
+    fn from_lisp(object: Object) -> Option<Self>;
+    fn from_missing() -> Option<Self> {
+        None
+    }
+}
+
+/// Conversion from a Rust value into a lisp object.
+pub trait ToLisp {
+    fn to_lisp(self) -> Object;
+}
+
+/// What a builtin made with `lisp_fn!` may return: a value, an optional value
+/// (`None` being no value, as for `print`), `()`, or a `Result` of those.
+pub trait IntoLispResult {
+    fn into_lisp_result(self) -> Result<Option<Object>, Condition>;
+}
+
+/// Converts the arg of a builtin, reporting a type error naming the arg.
+pub fn from_arg<T: FromLisp>(arg: Option<Object>, fnname: &str, argname: &str) -> Result<T, Condition> {
+    match arg {
+        Some(object) => {
+            match T::from_lisp(object.clone()) {
+                Some(value) => Ok(value),
+                None => Err(Condition::with_irritants("type-error",
+                                                      format!("{} expects {} for {}", fnname, T::type_name(), argname),
+                                                      vec![object])),
+            }
+        }
+        None => T::from_missing().ok_or(Condition::new("arity-error", format!("{} is missing {}", fnname, argname))),
+    }
+}
+
+/// Defines a builtin from a Rust function over convertible types.
+///
+/// The generated function of the same name returns a `BuiltinFn` called
+/// `$lisp_name`, whose arity comes from the params (trailing `Option` params
+/// are optional) and whose doc comes from the doc comment.
+///
+/// ```ignore
+/// lisp_fn! {
+///     /// (square n) multiplies n by itself.
+///     fn square["square"](n: i64) -> i64 {
+///         n * n
+///     }
+/// }
+/// env.define_builtin(square());
+/// ```
+#[macro_export]
+macro_rules! lisp_fn {
+    ($(#[doc = $doc:expr])* fn $name:ident [$lisp_name:expr] ($($arg:ident : $ty:ty),*) -> $ret:ty $body:block) => {
+        $(#[doc = $doc])*
+        pub fn $name() -> $crate::data::BuiltinFn {
+            fn inner($($arg: $ty),*) -> $ret $body
+            let optional: &[bool] = &[$(<$ty as $crate::convert::FromLisp>::OPTIONAL),*];
+            let required = optional.iter().take_while(|&&optional| !optional).count();
+            let arity = if required == optional.len() {
+                $crate::data::Arity::Exactly(required)
+            } else {
+                $crate::data::Arity::Range(required, optional.len())
+            };
+            let doc = [$($doc),*].iter().map(|line: &&str| line.trim()).collect::<Vec<_>>().join("\n");
+            $crate::data::BuiltinFn::from_closure($lisp_name, |args, _| {
+                #[allow(unused_mut, unused_variables)]
+                let mut args = args.into_iter();
+                $(let $arg: $ty = try!($crate::convert::from_arg(args.next(), $lisp_name, stringify!($arg)));)*
+                $crate::convert::IntoLispResult::into_lisp_result(inner($($arg),*))
+            }).with_arity(arity).with_doc(&doc)
+        }
+    }
+}
+
+impl FromLisp for Object {
+    fn type_name() -> String {
+        "any object".to_string()
+    }
+    fn from_lisp(object: Object) -> Option<Object> {
+        Some(object)
+    }
+}
+
+impl ToLisp for Object {
+    fn to_lisp(self) -> Object {
+        self
+    }
+}
+
+impl FromLisp for i64 {
+    fn type_name() -> String {
+        "integer".to_string()
+    }
+    fn from_lisp(object: Object) -> Option<i64> {
+        match object {
+            Object::Number(Number::Int(i)) => Some(i),
+            _ => None,
+        }
+    }
+}
+
+impl ToLisp for i64 {
+    fn to_lisp(self) -> Object {
+        Object::Number(Number::Int(self))
+    }
+}
+
+impl FromLisp for f64 {
+    fn type_name() -> String {
+        "number".to_string()
+    }
+    fn from_lisp(object: Object) -> Option<f64> {
+        match object {
+            Object::Number(Number::Int(i)) => Some(i as f64),
+            Object::Number(Number::Float(f, _)) => Some(f),
+            _ => None,
+        }
+    }
+}
+
+impl ToLisp for f64 {
+    fn to_lisp(self) -> Object {
+        Object::Number(Number::Float(self, self.to_string()))
+    }
+}
+
+impl FromLisp for String {
+    fn type_name() -> String {
+        "string".to_string()
+    }
+    fn from_lisp(object: Object) -> Option<String> {
+        match object {
+            Object::String(s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+impl ToLisp for String {
+    fn to_lisp(self) -> Object {
+        Object::String(self)
+    }
+}
+
+impl<'a> ToLisp for &'a str {
+    fn to_lisp(self) -> Object {
+        Object::String(self.to_string())
+    }
+}
+
+impl FromLisp for bool {
+    fn type_name() -> String {
+        "boolean".to_string()
+    }
+    fn from_lisp(object: Object) -> Option<bool> {
+        match object {
+            Object::Boolean(boolean) => Some(boolean.into()),
+            _ => None,
+        }
+    }
+}
+
+impl ToLisp for bool {
+    fn to_lisp(self) -> Object {
+        self.into()
+    }
+}
+
+impl FromLisp for Function {
+    fn type_name() -> String {
+        "function".to_string()
+    }
+    fn from_lisp(object: Object) -> Option<Function> {
+        match object {
+            Object::Function(function) => Some(function),
+            _ => None,
+        }
+    }
+}
+
+impl ToLisp for Function {
+    fn to_lisp(self) -> Object {
+        Object::Function(self)
+    }
+}
+
+impl FromLisp for Port {
+    fn type_name() -> String {
+        "port".to_string()
+    }
+    fn from_lisp(object: Object) -> Option<Port> {
+        match object {
+            Object::Port(port) => Some(port),
+            _ => None,
+        }
+    }
+}
+
+impl ToLisp for Port {
+    fn to_lisp(self) -> Object {
+        Object::Port(self)
+    }
+}
+
+impl<T: FromLisp> FromLisp for Vec<T> {
+    fn type_name() -> String {
+        format!("list of {}", T::type_name())
+    }
+    fn from_lisp(object: Object) -> Option<Vec<T>> {
+        match object {
+            Object::List(elems) => elems.into_iter().map(T::from_lisp).collect(),
+            _ => None,
+        }
+    }
+}
+
+impl<T: ToLisp> ToLisp for Vec<T> {
+    fn to_lisp(self) -> Object {
+        Object::List(Box::new(self.into_iter().map(ToLisp::to_lisp).collect()))
+    }
+}
+
+impl<K: FromLisp + Eq + Hash, V: FromLisp> FromLisp for HashMap<K, V> {
+    fn type_name() -> String {
+        "hash table".to_string()
+    }
+    fn from_lisp(object: Object) -> Option<HashMap<K, V>> {
+        match object {
+            Object::HashTable(table) => {
+                table.0
+                    .into_iter()
+                    .map(|(k, v)| K::from_lisp(k).and_then(|k| V::from_lisp(v).map(|v| (k, v))))
+                    .collect()
+            }
+            _ => None,
+        }
+    }
+}
+
+impl<K: ToLisp, V: ToLisp> ToLisp for HashMap<K, V> {
+    fn to_lisp(self) -> Object {
+        Object::HashTable(HashTable(self.into_iter().map(|(k, v)| (k.to_lisp(), v.to_lisp())).collect()))
+    }
+}
+
+impl<T: FromLisp> FromLisp for Option<T> {
+    const OPTIONAL: bool = true;
+    fn type_name() -> String {
+        T::type_name()
+    }
+    fn from_lisp(object: Object) -> Option<Option<T>> {
+        T::from_lisp(object).map(Some)
+    }
+    fn from_missing() -> Option<Option<T>> {
+        Some(None)
+    }
+}
+
+impl<T: ToLisp> IntoLispResult for T {
+    fn into_lisp_result(self) -> Result<Option<Object>, Condition> {
+        Ok(Some(self.to_lisp()))
+    }
+}
+
+impl<T: ToLisp> IntoLispResult for Option<T> {
+    fn into_lisp_result(self) -> Result<Option<Object>, Condition> {
+        Ok(self.map(ToLisp::to_lisp))
+    }
+}
+
+impl IntoLispResult for () {
+    fn into_lisp_result(self) -> Result<Option<Object>, Condition> {
+        Ok(None)
+    }
+}
+
+impl<T: IntoLispResult> IntoLispResult for Result<T, Condition> {
+    fn into_lisp_result(self) -> Result<Option<Object>, Condition> {
+        self.and_then(IntoLispResult::into_lisp_result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use data::*;
+    use std::collections::HashMap;
+    use super::{FromLisp, ToLisp};
+
+    lisp_fn! {
+        /// (repeat s n) is s n times.
+        fn repeat["repeat"](s: String, n: i64) -> String {
+            s.repeat(n as usize)
+        }
+    }
+
+    lisp_fn! {
+        /// (greet name [greeting]) greets name.
+        fn greet["greet"](name: String, greeting: Option<String>) -> String {
+            format!("{}, {}", greeting.unwrap_or("Hello".to_string()), name)
+        }
+    }
+
+    lisp_fn! {
+        /// (halve n) is n / 2, for even n.
+        fn halve["halve"](n: i64) -> Result<i64, Condition> {
+            match n % 2 {
+                0 => Ok(n / 2),
+                _ => Err(Condition::new("domain-error", "odd".to_string())),
+            }
+        }
+    }
+
+    fn call(builtin: BuiltinFn, args: Vec<Object>) -> Result<Option<Object>, Condition> {
+        builtin.call(args, &mut Env::new())
+    }
+
+    #[test]
+    fn arity_and_doc() {
+        assert_eq!(repeat().arity(), Arity::Exactly(2));
+        assert_eq!(greet().arity(), Arity::Range(1, 2));
+        assert_eq!(repeat().doc(), Some("(repeat s n) is s n times."));
+        assert_eq!(repeat().name(), "repeat");
+    }
+
+    #[test]
+    fn args_and_results() {
+        assert_eq!(call(repeat(), vec!["ab".to_lisp(), 3i64.to_lisp()]), Ok(Some("ababab".to_lisp())));
+        assert_eq!(call(greet(), vec!["Ann".to_lisp()]), Ok(Some("Hello, Ann".to_lisp())));
+        assert_eq!(call(greet(), vec!["Ann".to_lisp(), "Hi".to_lisp()]), Ok(Some("Hi, Ann".to_lisp())));
+        assert_eq!(call(halve(), vec![4i64.to_lisp()]), Ok(Some(2i64.to_lisp())));
+        assert_eq!(call(halve(), vec![3i64.to_lisp()]).unwrap_err().kind, "domain-error");
+    }
+
+    #[test]
+    fn type_errors() {
+        let condition = call(repeat(), vec!["ab".to_lisp(), "3".to_lisp()]).unwrap_err();
+        assert_eq!(condition.kind, "type-error");
+        assert_eq!(condition.message, "repeat expects integer for n");
+        assert_eq!(condition.irritants, vec!["3".to_lisp()]);
+        assert_eq!(call(repeat(), vec!["ab".to_lisp()]).unwrap_err().kind, "arity-error");
+    }
+
+    #[test]
+    fn conversions() {
+        assert_eq!(i64::from_lisp(7i64.to_lisp()), Some(7));
+        assert_eq!(f64::from_lisp(7i64.to_lisp()), Some(7.0));
+        assert_eq!(i64::from_lisp(1.5f64.to_lisp()), None);
+        assert_eq!(bool::from_lisp(true.to_lisp()), Some(true));
+        assert_eq!(Vec::<i64>::from_lisp(vec![1i64, 2].to_lisp()), Some(vec![1, 2]));
+        assert_eq!(Vec::<i64>::from_lisp(vec!["a"].to_lisp()), None);
+        assert_eq!(Vec::<String>::type_name(), "list of string");
+        let mut table = HashMap::new();
+        table.insert("a".to_string(), 1i64);
+        assert_eq!(HashMap::<String, i64>::from_lisp(table.clone().to_lisp()), Some(table));
+        assert_eq!(Option::<i64>::from_missing(), Some(None));
+        assert_eq!(i64::from_missing(), None);
+    }
+}
@@ -1,8 +1,20 @@
 use std::collections::HashMap;
 use stdlisp::BASE_FUNCTIONS;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use compiler::{Chunk, compile_function};
+use debug::{DEBUG_MAX_DEPTH, Debugging};
+use port::Port;
+use eval::eval_form;
+use lexer::line_column;
+use module::{Modules, Namespace, default_library_path};
+use parser::MAX_NESTING;
+use std::path::PathBuf;
+use std::mem;
 use std::hash::{Hash, Hasher};
+use std::collections::hash_map::DefaultHasher;
 use std::fmt;
+use std::time::{Duration, Instant};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::ops::{AddAssign, DivAssign, MulAssign, SubAssign, RemAssign};
 use std::ops::{Add, Sub, Mul, Div, Rem};
 
@@ -12,13 +24,83 @@ pub enum Inhibit {
     Stop(Option<String>),
 }
 
-#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+#[derive(Clone, Eq, PartialEq, Hash)]
 pub enum Expr {
     Expr(Object),
-    Exprs(Box<Vec<Expr>>),
+    /// A form, with where it was read from if it was.
+    Exprs(Box<Vec<Expr>>, Option<Span>),
+}
+
+/// Text that forms were read from.
+#[derive(Debug)]
+pub struct Source {
+    /// A file name, or a description such as `<repl>`.
+    pub name: String,
+    pub text: String,
+}
+
+/// Where a form was read from: the bytes `start..end` of `source`. Spans are
+/// not part of a form's value, so the same form read in two places is equal.
+#[derive(Clone)]
+pub struct Span {
+    pub source: Arc<Source>,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    /// The form as it was written.
+    pub fn text(&self) -> &str {
+        &self.source.text[self.start..self.end]
+    }
+    /// `name:line:column` of the start of the form.
+    pub fn position(&self) -> String {
+        let (line, column) = line_column(&self.source.text, self.start);
+        format!("{}:{}:{}", self.source.name, line, column)
+    }
+}
+
+impl PartialEq for Span {
+    fn eq(&self, _: &Span) -> bool {
+        true
+    }
+}
+
+impl Eq for Span {}
+
+impl Hash for Span {
+    fn hash<H: Hasher>(&self, _: &mut H) {}
+}
+
+impl fmt::Debug for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "{}", self.position())
+    }
+}
+
+// Spans are left out, so that forms print the same wherever they were read.
+impl fmt::Debug for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            Expr::Expr(ref object) => f.debug_tuple("Expr").field(object).finish(),
+            Expr::Exprs(ref exprs, _) => f.debug_tuple("Exprs").field(exprs).finish(),
+        }
+    }
 }
 
 impl Expr {
+    /// A form that was made rather than read, so has no span.
+    pub fn form(exprs: Vec<Expr>) -> Expr {
+        Expr::Exprs(Box::new(exprs), None)
+    }
+
+    pub fn span(&self) -> Option<&Span> {
+        match *self {
+            Expr::Exprs(_, ref span) => span.as_ref(),
+            _ => None,
+        }
+    }
+
     pub fn unwrap_expr(&self) -> Option<&Object> {
         match *self {
             Expr::Expr(ref object) => Some(object),
@@ -26,7 +108,42 @@ impl Expr {
         }
     }
 
+    /// `(quote expr)`.
+    pub fn quote(expr: Expr) -> Expr {
+        Expr::form(vec![Expr::Expr(Object::Symbol("quote".to_string())), expr])
+    }
+
+    /// The expr as data, as `quote` and `read` give it.
+    pub fn to_object(&self) -> Object {
+        match *self {
+            Expr::Expr(ref object) => object.clone(),
+            Expr::Exprs(ref exprs, _) => Object::List(Box::new(exprs.iter().map(|e| e.to_object()).collect())),
+        }
+    }
+
+    /// Data as code, for `eval`. Lists become forms, except the empty list.
+    pub fn from_object(object: &Object) -> Expr {
+        match *object {
+            Object::List(ref elems) if !elems.is_empty() => {
+                Expr::form(elems.iter().map(Expr::from_object).collect())
+            }
+            ref other => Expr::Expr(other.clone()),
+        }
+    }
+
+    fn is_quote(&self) -> bool {
+        match *self {
+            Expr::Exprs(ref exprs, _) => exprs.first() == Some(&Expr::Expr(Object::Symbol("quote".to_string()))),
+            _ => false,
+        }
+    }
+
+    // Symbols are substituted in quoted so they are not looked up as
+    // variables, and quoted data is left alone. Forms keep their spans.
     pub fn replace_all(&self, replacement_hm: &HashMap<&Object, &Object>) -> Expr {
+        if self.is_quote() {
+            return self.clone();
+        }
         match *self {
             Expr::Expr(Object::ConditionalCase(ref case, ref body)) => {
                 Expr::Expr(Object::ConditionalCase(
@@ -35,17 +152,20 @@ impl Expr {
                         ))
             }
             Expr::Expr(ref object) => {
-                if let Some(replacement) = replacement_hm.get(object) {
+                if let Some(&&Object::Symbol(ref name)) = replacement_hm.get(object) {
+                    Expr::quote(Expr::Expr(Object::Symbol(name.clone())))
+                } else if let Some(replacement) = replacement_hm.get(object) {
                     Expr::Expr((*replacement).clone())
                 } else {
                     (*self).clone()
                 }
             }
-            Expr::Exprs(ref exprs) => {
+            Expr::Exprs(ref exprs, ref span) => {
                 Expr::Exprs(Box::new((*exprs)
                                      .iter()
                                      .map(|e| e.replace_all(replacement_hm))
-                                     .collect::<Vec<_>>()))
+                                     .collect::<Vec<_>>()),
+                            span.clone())
             }
         }
     }
@@ -57,9 +177,14 @@ pub enum Object {
     String(String),
     Number(Number),
     Boolean(Boolean),
+    Nil,
     List(Box<Vec<Object>>),
+    HashTable(HashTable),
     ConditionalCase(Box<Expr>, Vec<Expr>),
     Function(Function),
+    Condition(Condition),
+    Port(Port),
+    Environment(Environment),
     Exit(Option<String>)
 }
 
@@ -82,6 +207,181 @@ impl Object {
             _ => None,
         }
     }
+    /// The name of the object's type, as used in error messages.
+    pub fn type_name(&self) -> &'static str {
+        match *self {
+            Object::Symbol(_) => "symbol",
+            Object::String(_) => "string",
+            Object::Number(Number::Int(_)) => "integer",
+            Object::Number(Number::Float(..)) => "float",
+            Object::Boolean(_) => "boolean",
+            Object::Nil => "nil",
+            Object::List(_) => "list",
+            Object::HashTable(_) => "hash table",
+            Object::ConditionalCase(..) => "conditional case",
+            Object::Function(_) => "function",
+            Object::Condition(_) => "condition",
+            Object::Port(_) => "port",
+            Object::Environment(_) => "environment",
+            Object::Exit(_) => "exit",
+        }
+    }
+}
+
+/// A table of objects to objects. Equal tables hash the same whatever order
+/// their entries are stored in.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct HashTable(pub HashMap<Object, Object>);
+
+impl Hash for HashTable {
+    fn hash<SipHasher>(&self, state: &mut SipHasher) where SipHasher: Hasher {
+        let mut combined: u64 = 0;
+        for entry in self.0.iter() {
+            let mut entry_hasher = DefaultHasher::new();
+            entry.hash(&mut entry_hasher);
+            combined = combined.wrapping_add(entry_hasher.finish());
+        }
+        combined.hash(state);
+    }
+}
+
+/// How many calls a condition's backtrace keeps; deeper ones are only counted.
+pub const MAX_BACKTRACE: usize = 64;
+
+/// A signalled error; builtin failures, `error` and `raise` all produce one.
+///
+/// The backtrace is not part of the error's value: conditions that differ only
+/// in where they were signalled are equal.
+#[derive(Debug, Clone)]
+pub struct Condition {
+    pub kind: String,
+    pub message: String,
+    pub irritants: Vec<Object>,
+    /// The calls in progress when the condition was signalled, innermost
+    /// first, up to `MAX_BACKTRACE` of them.
+    pub backtrace: Vec<CallSite>,
+    /// How many calls further out were left out of the backtrace.
+    pub omitted_calls: usize,
+}
+
+/// A call that a condition unwound through.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct CallSite {
+    /// The name the function was called by, or `lambda` if it had none.
+    pub function: String,
+    /// The call form, with the params of the function around it substituted
+    /// if the tree-walker evaluated it.
+    pub call: Expr,
+    /// Where the call was read from, if it was.
+    pub span: Option<Span>,
+}
+
+impl Condition {
+    pub fn new(kind: &str, message: String) -> Condition {
+        Condition::with_irritants(kind, message, Vec::new())
+    }
+    pub fn with_irritants(kind: &str, message: String, irritants: Vec<Object>) -> Condition {
+        Condition {
+            kind: kind.to_string(),
+            message: message,
+            irritants: irritants,
+            backtrace: Vec::new(),
+            omitted_calls: 0,
+        }
+    }
+    /// Records that the condition unwound out of `call`. Escapes to a
+    /// continuation are not errors and record nothing, and neither does
+    /// `cond`, which the bytecode compiler usually turns into jumps rather
+    /// than a call, so that both evaluators give the same backtrace.
+    pub fn called_from(mut self, call: &Expr) -> Condition {
+        if self.kind == "continuation" {
+            return self;
+        }
+        let site = CallSite::new(call);
+        if site.function == "cond" {
+            return self;
+        }
+        if self.backtrace.len() == MAX_BACKTRACE {
+            self.omitted_calls += 1;
+            return self;
+        }
+        self.backtrace.push(site);
+        self
+    }
+    // Continuations escape by unwinding the Rust stack as a condition that
+    // only the matching call/cc will stop; it reaches the top level only when
+    // the continuation is invoked after call/cc has already returned.
+    pub fn escape(id: usize, value: Option<Object>) -> Condition {
+        let mut irritants = vec![Object::Number(Number::Int(id as i64))];
+        irritants.extend(value);
+        Condition::with_irritants("continuation",
+                                  format!("Continuation {} invoked outside of its dynamic extent", id),
+                                  irritants)
+    }
+    /// If this is an escape to continuation `id`, the value passed to it.
+    pub fn escaped_value(&self, id: usize) -> Option<Option<Object>> {
+        if self.kind == "continuation" && self.irritants.first() == Some(&Object::Number(Number::Int(id as i64))) {
+            Some(self.irritants.get(1).cloned())
+        } else {
+            None
+        }
+    }
+    pub fn is_catchable(&self) -> bool {
+        self.kind != "continuation" && self.kind != "resource-exhausted"
+    }
+}
+
+impl CallSite {
+    pub fn new(call: &Expr) -> CallSite {
+        let function = match *call {
+            Expr::Exprs(ref exprs, _) => {
+                match exprs.first() {
+                    Some(&Expr::Expr(Object::Symbol(ref name))) => name.clone(),
+                    _ => "lambda".to_string(),
+                }
+            }
+            _ => "lambda".to_string(),
+        };
+        CallSite {
+            function: function,
+            call: call.clone(),
+            span: call.span().cloned(),
+        }
+    }
+}
+
+impl PartialEq for Condition {
+    fn eq(&self, other: &Condition) -> bool {
+        self.kind == other.kind && self.message == other.message && self.irritants == other.irritants
+    }
+}
+
+impl Eq for Condition {}
+
+impl Hash for Condition {
+    fn hash<SipHasher>(&self, state: &mut SipHasher) where SipHasher: Hasher {
+        self.kind.hash(state);
+        self.message.hash(state);
+        self.irritants.hash(state);
+    }
+}
+
+impl From<String> for Condition {
+    fn from(message: String) -> Condition {
+        Condition::new("error", message)
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        try!(write!(f, "{}: {}", self.kind, self.message));
+        if self.is_catchable() {
+            for irritant in self.irritants.iter() {
+                try!(write!(f, " {:?}", irritant));
+            }
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
@@ -191,9 +491,66 @@ impl PartialEq for Number {
 }
 
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Evaluator {
+    TreeWalk,
+    Bytecode,
+}
+
+/// Bounds on the work a single input may do before it is stopped with a
+/// resource-exhausted condition. `None` means unlimited.
+#[derive(Debug, Clone, Default)]
+pub struct Limits {
+    pub max_steps: Option<usize>,
+    /// How deeply calls may nest. The tree-walker recurses on the Rust stack,
+    /// taking about 30KB of it per call in a debug build and 6KB in a release
+    /// build, so the thread it runs on needs a stack to match.
+    pub max_depth: Option<usize>,
+    pub max_objects: Option<usize>,
+    pub timeout: Option<Duration>,
+}
+
+/// The call depth limit of a new `Env`. The tree-walker needs a 32MB stack to
+/// reach it in a debug build, or 8MB in a release build; the bytecode vm keeps
+/// its calls off the Rust stack. The rlisp binary evaluates on a thread with a
+/// 512MB stack and raises the limit to 10000.
+pub const DEFAULT_MAX_DEPTH: usize = 1000;
+
+#[derive(Debug, Clone, Default)]
+struct Usage {
+    steps: usize,
+    depth: usize,
+    // forms the tree-walker is inside in the current call's body
+    forms: usize,
+    objects: usize,
+    started: Option<Instant>,
+}
+
+// how many steps go by between checks of the clock
+const TIMEOUT_CHECK_INTERVAL: usize = 1024;
+
 #[derive(Clone)]
 pub struct Env {
-    pub variables: HashMap<String, Object>
+    pub variables: HashMap<String, Object>,
+    pub evaluator: Evaluator,
+    pub limits: Limits,
+    /// Where `read-line` and `read-char` read and `print` and `write-string`
+    /// write when not given a port.
+    pub input: Port,
+    pub output: Port,
+    /// Where `require` looks for modules after the requiring module's own
+    /// directory; taken from `RLISP_PATH`.
+    pub library_path: Vec<PathBuf>,
+    pub modules: Modules,
+    /// The namespace of the module whose function is running, where names are
+    /// looked up before `variables`.
+    pub namespace: Option<Namespace>,
+    /// Whether `define` prints a warning to stderr when it replaces a binding.
+    pub warn_on_redefine: bool,
+    /// The debugger, if one is attached; forms are then evaluated by the
+    /// tree-walker whatever `evaluator` says.
+    pub debugging: Option<Debugging>,
+    usage: Usage,
 }
 
 #[allow(dead_code)]
@@ -206,73 +563,321 @@ impl Env {
                     hm.insert(name.to_string(), Object::Function((*func).clone()));
                 }
                 hm
-            }
+            },
+            evaluator: Evaluator::Bytecode,
+            limits: Limits {
+                max_depth: Some(DEFAULT_MAX_DEPTH),
+                ..Limits::default()
+            },
+            input: Port::stdin(),
+            output: Port::stdout(),
+            library_path: default_library_path(),
+            modules: Modules::default(),
+            namespace: None,
+            warn_on_redefine: false,
+            debugging: None,
+            usage: Usage::default(),
         }
     }
     pub fn with_functions(functions: Vec<(String, Function)>) -> Env {
-        Env {
-            variables: {
-                let mut hm = HashMap::new();
-                for &(name, ref func) in BASE_FUNCTIONS.iter() {
-                    hm.insert(name.to_string(), Object::Function((*func).clone()));
-                }
-                for (name, func) in functions {
-                    hm.insert(name, Object::Function(func));
-                }
-                hm
-            }
+        let mut env = Env::new();
+        for (name, func) in functions {
+            env.variables.insert(name, Object::Function(func));
         }
+        env
     }
     pub fn variables(&self) -> HashMap<String, Object> {
         self.variables.clone()
     }
-    pub fn get_variable(&self, varname: &String) -> Object {
-        self.variables[varname].clone()
+    pub fn get_variable(&self, varname: &String) -> Option<Object> {
+        self.lookup(varname)
+    }
+    /// The value of `name` in the current module's namespace, if it is bound
+    /// there, or else at top level.
+    pub fn lookup(&self, name: &str) -> Option<Object> {
+        if let Some(ref namespace) = self.namespace {
+            if let Some(value) = namespace.private(name) {
+                return Some(value);
+            }
+            if let Some(value) = namespace.qualify(name).and_then(|qualified| self.variables.get(&qualified)) {
+                return Some(value.clone());
+            }
+        }
+        self.variables.get(name).cloned()
     }
     pub fn var_exists(&self, varname: &String) -> bool {
         self.variables.contains_key(varname)
     }
+    /// Resets the usage counted against `limits`; called before each top-level form.
+    pub fn start_evaluation(&mut self) {
+        self.usage = Usage {
+            started: Some(Instant::now()),
+            ..Usage::default()
+        };
+        if let Some(ref mut debugging) = self.debugging {
+            debugging.reset();
+        }
+    }
+    /// Leaves `paused` out of the time counted against `limits.timeout`, as
+    /// when evaluation waited that long on a debugger.
+    pub fn discount_time(&mut self, paused: Duration) {
+        if let Some(ref mut started) = self.usage.started {
+            *started += paused;
+        }
+    }
+    pub fn step(&mut self) -> Result<(), Condition> {
+        self.usage.steps += 1;
+        if let Some(max_steps) = self.limits.max_steps {
+            if self.usage.steps > max_steps {
+                return Err(exhausted(format!("Evaluation exceeded {} steps", max_steps)));
+            }
+        }
+        if let (Some(timeout), Some(started)) = (self.limits.timeout, self.usage.started) {
+            if self.usage.steps % TIMEOUT_CHECK_INTERVAL == 0 && started.elapsed() > timeout {
+                return Err(exhausted(format!("Evaluation exceeded {:?}", timeout)));
+            }
+        }
+        Ok(())
+    }
+    pub fn enter_call(&mut self) -> Result<(), Condition> {
+        let max_depth = match (self.limits.max_depth, self.debugging.is_some()) {
+            (Some(max_depth), true) => Some(max_depth.min(DEBUG_MAX_DEPTH)),
+            (None, true) => Some(DEBUG_MAX_DEPTH),
+            (max_depth, false) => max_depth,
+        };
+        if let Some(max_depth) = max_depth {
+            if self.usage.depth >= max_depth {
+                return Err(exhausted(format!("Evaluation exceeded a call depth of {}", max_depth)));
+            }
+        }
+        self.usage.depth += 1;
+        Ok(())
+    }
+    pub fn exit_call(&mut self) {
+        self.usage.depth = self.usage.depth.saturating_sub(1);
+    }
+    /// Counts a form the tree-walker evaluates inside another. Each call's
+    /// body may nest forms `MAX_NESTING` deep, as deep as source can.
+    pub fn enter_form(&mut self) -> Result<(), Condition> {
+        if self.usage.forms >= MAX_NESTING {
+            return Err(exhausted(format!("Evaluation nested forms more than {} deep", MAX_NESTING)));
+        }
+        self.usage.forms += 1;
+        Ok(())
+    }
+    pub fn exit_form(&mut self) {
+        self.usage.forms = self.usage.forms.saturating_sub(1);
+    }
+    /// Starts counting forms afresh for a call's body, returning the caller's
+    /// count for `exit_body` to restore.
+    pub fn enter_body(&mut self) -> usize {
+        mem::replace(&mut self.usage.forms, 0)
+    }
+    pub fn exit_body(&mut self, forms: usize) {
+        self.usage.forms = forms;
+    }
+    /// Counts an object produced during evaluation, including list elements.
+    pub fn allocate(&mut self, object: &Object) -> Result<(), Condition> {
+        self.usage.objects += match *object {
+            Object::List(ref elems) => elems.len() + 1,
+            _ => 1,
+        };
+        if let Some(max_objects) = self.limits.max_objects {
+            if self.usage.objects > max_objects {
+                return Err(exhausted(format!("Evaluation exceeded {} allocated objects", max_objects)));
+            }
+        }
+        Ok(())
+    }
+    /// Binds a builtin under its own name, replacing any existing binding.
+    pub fn define_builtin(&mut self, builtin: BuiltinFn) {
+        self.variables.insert(builtin.name().to_string(), Object::Function(Function::builtin(builtin)));
+    }
+    pub fn get_function(&self, name: &str) -> Option<Function> {
+        match self.lookup(name) {
+            Some(Object::Function(function)) => Some(function),
+            _ => None,
+        }
+    }
+    /// Binds `var`, replacing any existing binding.
     pub fn add_variable(&mut self, var: String, value: Object) {
-        if self.variables().keys().any(|x| x == &var) {
-            panic!(format!("Variable {:?} cannot be set because it already exists in current env.", var));
-        } else {
-            self.variables.insert(var, value);
+        if self.warn_on_redefine && self.variables.contains_key(&var) {
+            eprintln!("warning: redefining {}", var);
         }
+        self.variables.insert(var, value);
     }
-    pub fn set_variable(&mut self, var: String, value: Object) {
-        if !self.variables().keys().any(|x| x == &var) {
-            panic!("Variable {:?} cannot be changed because it does not exist.");
-        } else {
-            *self.variables.get_mut(&var).unwrap() = value;
+    /// Changes the value of `var`, which must already be bound.
+    pub fn set_variable(&mut self, mut var: String, value: Object) -> Result<(), Condition> {
+        if let Some(ref namespace) = self.namespace {
+            if namespace.private(&var).is_some() {
+                namespace.set_private(&var, value);
+                return Ok(());
+            }
+            if let Some(qualified) = namespace.qualify(&var).filter(|qualified| self.variables.contains_key(qualified)) {
+                var = qualified;
+            }
+        }
+        match self.variables.get_mut(&var) {
+            Some(binding) => {
+                *binding = value;
+                Ok(())
+            }
+            None => Err(Condition::new("unbound-variable", format!("Cannot set! unbound variable {}", var))),
+        }
+    }
+}
+
+static NEXT_ENVIRONMENT: AtomicUsize = AtomicUsize::new(0);
+
+/// A set of top-level bindings that `eval` can be given to evaluate in,
+/// instead of the env it is called from. Copies share their bindings.
+#[derive(Clone)]
+pub struct Environment {
+    id: usize,
+    variables: Arc<Mutex<HashMap<String, Object>>>,
+}
+
+impl Environment {
+    pub fn new(variables: HashMap<String, Object>) -> Environment {
+        Environment {
+            id: NEXT_ENVIRONMENT.fetch_add(1, Ordering::SeqCst),
+            variables: Arc::new(Mutex::new(variables)),
         }
     }
+    /// Evaluates `expr` in these bindings, with the limits, ports and
+    /// evaluator of `env`. Definitions made by `expr` are kept here.
+    pub fn eval(&self, expr: &Expr, env: &mut Env) -> Result<Option<Object>, Condition> {
+        let variables = self.variables.lock().unwrap().clone();
+        let caller_variables = mem::replace(&mut env.variables, variables);
+        let result = eval_form(expr, env);
+        let variables = mem::replace(&mut env.variables, caller_variables);
+        *self.variables.lock().unwrap() = variables;
+        result
+    }
+    pub fn get(&self, name: &str) -> Option<Object> {
+        self.variables.lock().unwrap().get(name).cloned()
+    }
+    /// Binds `name` here, replacing any existing binding.
+    pub fn define(&self, name: &str, value: Object) {
+        self.variables.lock().unwrap().insert(name.to_string(), value);
+    }
+}
+
+impl Hash for Environment {
+    fn hash<SipHasher>(&self, state: &mut SipHasher) where SipHasher: Hasher {
+        self.id.hash(state);
+    }
+}
+
+impl Eq for Environment {}
+
+impl PartialEq for Environment {
+    fn eq(&self, other: &Environment) -> bool {
+        self.id == other.id
+    }
+}
+
+impl fmt::Debug for Environment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "Environment {{{}}}", self.id)
+    }
+}
+
+fn exhausted(message: String) -> Condition {
+    Condition::new("resource-exhausted", message)
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Hash)]
 pub enum LispFn {
     Builtin(BuiltinFn),
-    UserDef(Vec<Object>, Vec<Expr>), // input vars, body
+    UserDef(Vec<Object>, Vec<Expr>, CodeCache), // input vars, body, compiled body
+    Continuation(usize), // id of the call/cc that created it
 }
 
-pub type BuiltinFnSignature = fn(Vec<Object>, &mut Env) -> Result<Option<Object>, String>;
+pub type BuiltinFnSignature = fn(Vec<Object>, &mut Env) -> Result<Option<Object>, Condition>;
+
+/// A builtin backed by a closure, so host programs can expose functions that
+/// capture state.
+pub type BuiltinClosure = Arc<dyn Fn(Vec<Object>, &mut Env) -> Result<Option<Object>, Condition> + Send + Sync>;
+
+/// How many args a builtin accepts; checked before the builtin is run.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum Arity {
+    Exactly(usize),
+    AtLeast(usize),
+    Range(usize, usize),
+    Any,
+}
+
+impl Arity {
+    pub fn accepts(&self, args: usize) -> bool {
+        match *self {
+            Arity::Exactly(n) => args == n,
+            Arity::AtLeast(n) => args >= n,
+            Arity::Range(min, max) => args >= min && args <= max,
+            Arity::Any => true,
+        }
+    }
+}
+
+impl fmt::Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            Arity::Exactly(1) => write!(f, "1 arg"),
+            Arity::Exactly(n) => write!(f, "{} args", n),
+            Arity::AtLeast(n) => write!(f, "at least {} args", n),
+            Arity::Range(min, max) => write!(f, "{} to {} args", min, max),
+            Arity::Any => write!(f, "any number of args"),
+        }
+    }
+}
 
 pub struct BuiltinFn {
     name: String,
-    inner: BuiltinFnSignature
+    inner: BuiltinClosure,
+    arity: Arity,
+    doc: Option<String>,
 }
 
 impl BuiltinFn {
     pub fn new(name: &str, func: BuiltinFnSignature) -> BuiltinFn {
+        BuiltinFn::from_closure(name, func)
+    }
+    pub fn from_closure<F>(name: &str, func: F) -> BuiltinFn
+        where F: Fn(Vec<Object>, &mut Env) -> Result<Option<Object>, Condition> + Send + Sync + 'static
+    {
         BuiltinFn {
             name: name.to_string(),
-            inner: func
+            inner: Arc::new(func),
+            arity: Arity::Any,
+            doc: None,
         }
     }
-    fn name(&self) -> &str {
+    pub fn with_arity(mut self, arity: Arity) -> BuiltinFn {
+        self.arity = arity;
+        self
+    }
+    pub fn with_doc(mut self, doc: &str) -> BuiltinFn {
+        self.doc = Some(doc.to_string());
+        self
+    }
+    pub fn name(&self) -> &str {
         &self.name
     }
-    pub fn inner(&self) -> BuiltinFnSignature {
-        self.inner
+    pub fn arity(&self) -> Arity {
+        self.arity
+    }
+    pub fn doc(&self) -> Option<&str> {
+        self.doc.as_ref().map(|doc| doc.as_str())
+    }
+    pub fn inner(&self) -> &BuiltinClosure {
+        &self.inner
+    }
+    pub fn call(&self, args: Vec<Object>, env: &mut Env) -> Result<Option<Object>, Condition> {
+        if !self.arity.accepts(args.len()) {
+            return Err(Condition::new("arity-error", format!("{} takes {}; got {}", self.name, self.arity, args.len())));
+        }
+        (self.inner)(args, env)
     }
 }
 
@@ -294,7 +899,9 @@ impl Clone for BuiltinFn {
     fn clone(&self) -> Self {
         BuiltinFn {
             name: self.name.clone(),
-            inner: self.inner
+            inner: self.inner.clone(),
+            arity: self.arity,
+            doc: self.doc.clone(),
         }
     }
 }
@@ -305,14 +912,72 @@ impl fmt::Debug for BuiltinFn {
     }
 }
 
-#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+/// The bytecode for a user function's body, compiled on its first call by the vm.
+/// It is derived from the body, so it takes no part in equality or hashing.
+pub struct CodeCache(Mutex<Option<Arc<Chunk>>>);
+
+impl CodeCache {
+    pub fn new() -> CodeCache {
+        CodeCache(Mutex::new(None))
+    }
+    pub fn get_or_compile(&self, vars: &[Object], body: &[Expr]) -> Arc<Chunk> {
+        let mut cached = self.0.lock().unwrap();
+        if let Some(ref chunk) = *cached {
+            return chunk.clone();
+        }
+        let chunk = Arc::new(compile_function(vars, body));
+        *cached = Some(chunk.clone());
+        chunk
+    }
+}
+
+impl Hash for CodeCache {
+    fn hash<SipHasher>(&self, _: &mut SipHasher) where SipHasher: Hasher {}
+}
+
+impl Eq for CodeCache {}
+
+impl PartialEq for CodeCache {
+    fn eq(&self, _: &CodeCache) -> bool {
+        true
+    }
+}
+
+impl Clone for CodeCache {
+    fn clone(&self) -> Self {
+        CodeCache(Mutex::new(self.0.lock().unwrap().clone()))
+    }
+}
+
+impl fmt::Debug for CodeCache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "CodeCache")
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Hash)]
 pub struct Function {
     pub procedure: Arc<LispFn>,
+    /// The namespace of the module the function was made in, which its body
+    /// looks names up in.
+    pub namespace: Option<Namespace>,
+}
+
+impl fmt::Debug for Function {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        f.debug_struct("Function").field("procedure", &self.procedure).finish()
+    }
 }
 
 //TODO
 impl Function {
-    pub fn from_exprs(declaration_vars: &[Expr], body: &[Expr]) -> Result<Function, String> {
+    pub fn builtin(builtin: BuiltinFn) -> Function {
+        Function {
+            procedure: Arc::new(LispFn::Builtin(builtin)),
+            namespace: None,
+        }
+    }
+    pub fn from_exprs(declaration_vars: &[Expr], body: &[Expr]) -> Result<Function, Condition> {
         let mut vars = Vec::new();
         for var in declaration_vars {
             match var.unwrap_expr() {
@@ -320,17 +985,26 @@ impl Function {
                     if let Object::Symbol(_) = *obj {
                         vars.push((*obj).clone());
                     } else {
-                        return Err(format!("Invalid var name {:?}", var));
+                        return Err(Condition::new("syntax-error", format!("Invalid var name {:?}", var)));
                     }
                 }
-                None => return Err(format!("Invalid var name {:?}", var)),
+                None => return Err(Condition::new("syntax-error", format!("Invalid var name {:?}", var))),
             }
         }
         Ok(Function {
             procedure: Arc::new(LispFn::UserDef(
                 vars,
-                body.iter().map(|ref e| (*e).clone()).collect::<Vec<Expr>>()
+                body.iter().map(|ref e| (*e).clone()).collect::<Vec<Expr>>(),
+                CodeCache::new()
             )),
+            namespace: None,
         })
     }
+    /// The same function, looking names up in `namespace`.
+    pub fn in_namespace(self, namespace: Option<Namespace>) -> Function {
+        Function {
+            procedure: self.procedure,
+            namespace: namespace,
+        }
+    }
 }
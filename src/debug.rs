@@ -0,0 +1,187 @@
+//! Pausing evaluation for a debugger.
+//!
+//! A debugger is a function the host attaches to an env. While one is
+//! attached, forms are evaluated by the tree-walker, which keeps every call on
+//! the Rust stack, and the user-defined functions being run are tracked along
+//! with the values of their params. Evaluation pauses at `(break)` and, when
+//! the debugger asked to step, before the next call; the debugger is then
+//! called with where it paused and says how to go on.
+
+use data::*;
+use eval::apply_function;
+use std::sync::Arc;
+use std::time::Instant;
+
+/// How deeply calls may nest while a debugger is attached, if the env's limit
+/// is deeper. A pause runs the debugger on top of the calls being run, and
+/// runaway recursion is better stopped before it is stepped through.
+pub const DEBUG_MAX_DEPTH: usize = 1000;
+
+/// What the host does when evaluation pauses, returning how to go on. While it
+/// runs, the debugger is detached from the env it is given, so evaluating
+/// there does not pause, and the time it takes does not count against the
+/// env's timeout.
+pub type Debugger = Arc<dyn Fn(&Pause, &mut Env) -> Step + Send + Sync>;
+
+/// How to go on after a pause.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Step {
+    /// Pause before the next call, including calls made to evaluate the args
+    /// of this one.
+    In,
+    /// Finish the call paused before, pausing before the call after it.
+    Over,
+    /// Run until the next `(break)`.
+    Continue,
+}
+
+/// A user-defined function being run.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub site: CallSite,
+    /// Its params and the values they were given.
+    pub locals: Vec<(String, Object)>,
+}
+
+/// Where evaluation paused.
+pub struct Pause<'a> {
+    /// The call about to be evaluated, or `None` at a `(break)`.
+    pub call: Option<&'a Expr>,
+    /// Where the call or `(break)` was read from, if it was.
+    pub span: Option<&'a Span>,
+    /// The functions being run, innermost last; the call is in the body of
+    /// the last one, or at top level if there are none.
+    pub frames: &'a [Frame],
+}
+
+/// The debugger attached to an env and what it is doing.
+#[derive(Clone)]
+pub struct Debugging {
+    debugger: Debugger,
+    frames: Vec<Frame>,
+    // how many calls are being evaluated, args included
+    depth: usize,
+    stepping: Stepping,
+    // where the last `(break)` evaluated was read from
+    breaking: Option<Span>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Stepping {
+    Continue,
+    In,
+    // pause before a call made at this depth or less
+    Over(usize),
+}
+
+impl Debugging {
+    pub fn new(debugger: Debugger) -> Debugging {
+        Debugging {
+            debugger: debugger,
+            frames: Vec::new(),
+            depth: 0,
+            stepping: Stepping::Continue,
+            breaking: None,
+        }
+    }
+    /// Stops stepping, so that the next form runs until a `(break)`.
+    pub fn reset(&mut self) {
+        self.frames.clear();
+        self.depth = 0;
+        self.stepping = Stepping::Continue;
+    }
+}
+
+/// Notes that `call` is about to be evaluated, pausing first if stepping.
+/// Each `enter` is followed by an `exit` once the call has been evaluated.
+pub fn enter(call: &Expr, env: &mut Env) {
+    let pause = match env.debugging {
+        Some(ref debugging) => {
+            match debugging.stepping {
+                Stepping::Continue => false,
+                Stepping::In => true,
+                Stepping::Over(depth) => debugging.depth <= depth,
+            }
+        }
+        None => return,
+    };
+    // `(break)` pauses by itself
+    if is_break(call) {
+        with_debugging(env, |debugging| debugging.breaking = call.span().cloned());
+    } else if pause {
+        pause_at(Some(call), env);
+    }
+    with_debugging(env, |debugging| debugging.depth += 1);
+}
+
+pub fn exit(env: &mut Env) {
+    with_debugging(env, |debugging| debugging.depth -= 1);
+}
+
+/// Applies `function` for `call`, keeping track of it while it runs if it is
+/// user-defined.
+pub fn apply(function: Function, args: Vec<Object>, call: &Expr, env: &mut Env) -> Result<Option<Object>, Condition> {
+    let locals = match *function.procedure {
+        LispFn::UserDef(ref vars, _, _) => {
+            let names = vars.iter().map(|var| {
+                match *var {
+                    Object::Symbol(ref name) => name.clone(),
+                    ref other => format!("{:?}", other),
+                }
+            });
+            names.zip(args.iter().cloned()).collect()
+        }
+        _ => return apply_function(function, args, env),
+    };
+    with_debugging(env, |debugging| {
+        debugging.frames.push(Frame {
+            site: CallSite::new(call),
+            locals: locals,
+        })
+    });
+    let result = apply_function(function, args, env);
+    with_debugging(env, |debugging| {
+        debugging.frames.pop();
+    });
+    result
+}
+
+/// Pauses for the debugger attached to `env`, if there is one.
+pub fn pause_at(call: Option<&Expr>, env: &mut Env) {
+    let mut debugging = match env.debugging.take() {
+        Some(debugging) => debugging,
+        None => return,
+    };
+    let paused = Instant::now();
+    let step = {
+        let pause = Pause {
+            call: call,
+            span: match call {
+                Some(call) => call.span(),
+                None => debugging.breaking.as_ref(),
+            },
+            frames: &debugging.frames,
+        };
+        (debugging.debugger)(&pause, env)
+    };
+    env.discount_time(paused.elapsed());
+    debugging.stepping = match step {
+        Step::In => Stepping::In,
+        Step::Over => Stepping::Over(debugging.depth),
+        Step::Continue => Stepping::Continue,
+    };
+    env.debugging = Some(debugging);
+}
+
+fn is_break(call: &Expr) -> bool {
+    match *call {
+        Expr::Exprs(ref exprs, _) => exprs.first() == Some(&Expr::Expr(Object::Symbol("break".to_string()))),
+        _ => false,
+    }
+}
+
+fn with_debugging<F: FnOnce(&mut Debugging)>(env: &mut Env, f: F) {
+    if let Some(ref mut debugging) = env.debugging {
+        f(debugging);
+    }
+}
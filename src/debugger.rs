@@ -0,0 +1,157 @@
+//! The debugger behind `--debug`: a repl that evaluation pauses in, at
+//! `(break)` and while stepping.
+//!
+//! Input is evaluated as if it were in the body of the paused function, with
+//! its params bound; lines starting with `,` are commands.
+
+use backtrace::{print_calls, substitute};
+use repl::LineReader;
+use rlisp::{CallSite, Interpreter, Object};
+use rlisp::data::{Env, Expr};
+use rlisp::debug::{Frame, Pause, Step};
+use rlisp::eval::eval_form;
+use rlisp::lexer::is_complete;
+use rlisp::parser::parse;
+use rustyline::error::ReadlineError;
+use std::mem;
+use std::sync::Mutex;
+use style;
+
+// in the home directory, apart from the repl's
+const HISTORY: &'static str = ".rlisp_debug_history";
+
+// name, description
+const COMMANDS: [(&'static str, &'static str); 6] = [
+    ("step", "Pause before the next call, stepping into this one"),
+    ("over", "Run this call, pausing before the one after it"),
+    ("continue", "Run until the next (break)"),
+    ("locals", "List the params of the paused function"),
+    ("backtrace", "List the calls being run, innermost first"),
+    ("help", "List the commands; any that start with what is typed run"),
+];
+
+/// Attaches the debugger to `interpreter`.
+pub fn attach(interpreter: &mut Interpreter) {
+    // one reader for every pause, so that history carries from one to the next
+    let reader = Mutex::new(None);
+    interpreter.attach_debugger(move |pause, env| {
+        let mut reader = reader.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let reader = reader.get_or_insert_with(|| LineReader::new(HISTORY));
+        let step = debug(pause, env, reader);
+        reader.save_history();
+        step
+    });
+}
+
+fn debug(pause: &Pause, env: &mut Env, reader: &mut LineReader) -> Step {
+    // the paused call first, then the calls to the functions around it
+    let mut here = match pause.call {
+        Some(call) => CallSite::new(call),
+        None => CallSite::new(&Expr::form(vec![Expr::Expr(Object::Symbol("break".to_string()))])),
+    };
+    here.span = pause.span.cloned();
+    let mut calls = vec![here];
+    calls.extend(pause.frames.iter().rev().map(|frame| frame.site.clone()));
+    println!("{}", describe(pause, &calls));
+    let mut input = String::new();
+    loop {
+        let prompt = if input.is_empty() { "debug> " } else { "...    " };
+        match reader.read_line(prompt) {
+            Ok(line) => {
+                input.push_str(&line);
+                input.push('\n');
+            }
+            Err(ReadlineError::Interrupted) => {
+                input.clear();
+                continue;
+            }
+            // Ctrl-D lets the program run on
+            Err(_) => return Step::Continue,
+        }
+        if input.trim().is_empty() {
+            input.clear();
+            continue;
+        }
+        let trimmed = input.trim().to_string();
+        if trimmed.starts_with(',') {
+            input.clear();
+            if let Some(step) = run_command(&trimmed[1..], pause, &calls) {
+                return step;
+            }
+        } else if is_complete(&input) {
+            let complete = mem::replace(&mut input, String::new());
+            eval_in_frame(&complete, pause.frames.last(), env);
+        }
+    }
+}
+
+// Where evaluation paused, such as "Paused before (f x) at a.lisp:3:5 in g".
+fn describe(pause: &Pause, calls: &[CallSite]) -> String {
+    let what = if pause.call.is_some() { "before" } else { "at" };
+    let mut description = match pause.span {
+        Some(span) => format!("Paused {} {} at {}", what, span.text(), span.position()),
+        None if pause.call.is_some() => format!("Paused before a call to {}", calls[0].function),
+        None => "Paused at (break)".to_string(),
+    };
+    if let Some(frame) = pause.frames.last() {
+        description.push_str(&format!(" in {}", frame.site.function));
+    }
+    description
+}
+
+// Runs a debugger command, returning how to go on if it was a step.
+fn run_command(input: &str, pause: &Pause, calls: &[CallSite]) -> Option<Step> {
+    let name = input.trim();
+    let matches = COMMANDS.iter().filter(|command| !name.is_empty() && command.0.starts_with(name)).collect::<Vec<_>>();
+    let command = match matches.len() {
+        1 => matches[0].0,
+        0 => {
+            println!("Unknown command ,{}; ,help lists the commands", name);
+            return None;
+        }
+        _ => {
+            let names = matches.iter().map(|command| format!(",{}", command.0)).collect::<Vec<_>>();
+            println!("Ambiguous command ,{}: could be {}", name, names.join(" or "));
+            return None;
+        }
+    };
+    match command {
+        "step" => return Some(Step::In),
+        "over" => return Some(Step::Over),
+        "continue" => return Some(Step::Continue),
+        "locals" => {
+            match pause.frames.last() {
+                Some(frame) if !frame.locals.is_empty() => {
+                    for &(ref name, ref value) in frame.locals.iter() {
+                        println!("{} = {:?}", name, value);
+                    }
+                }
+                Some(frame) => println!("{} has no params", frame.site.function),
+                None => println!("Paused at top level, where there are no params"),
+            }
+        }
+        "backtrace" => print_calls(calls, 0),
+        _ => {
+            for &(name, description) in COMMANDS.iter() {
+                println!("  ,{:<10} {}", name, description);
+            }
+        }
+    }
+    None
+}
+
+// Evaluates `input` with the params of `frame` bound, printing each result.
+fn eval_in_frame(input: &str, frame: Option<&Frame>, env: &mut Env) {
+    let exprs = match parse(&input.to_string()) {
+        Ok(exprs) => exprs,
+        Err(e) => return println!("{}", style::paint(style::ERROR, &e)),
+    };
+    let locals = frame.map(|frame| &frame.locals[..]).unwrap_or(&[]);
+    for expr in exprs {
+        match eval_form(&substitute(&expr, locals), env) {
+            Ok(Some(result)) => println!("{:?}", result),
+            Ok(None) => {}
+            Err(condition) => println!("{}", style::paint(style::ERROR, &condition.to_string())),
+        }
+    }
+}
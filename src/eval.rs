@@ -1,56 +1,41 @@
 use data::*;
-use parser::{parse_file, parse};
+use debug;
 use std::collections::HashMap;
+use std::mem;
+use vm;
 
-#[cfg(windows)]
-const NEWL: &'static str = "{nl}";
-#[cfg(not(windows))]
-const NEWL: &'static str = "\n";
+/// Forms evaluated by the evaluator itself rather than by a builtin, whose
+/// args are not evaluated first.
+pub const SPECIAL_FORMS: [&'static str; 6] = ["define", "lambda", "quote", "set!", "guard", "module"];
 
-pub fn run_file(file: &str, env: &mut Env) {
-    let parsed = parse_file(file);
-    if let Inhibit::Stop(exit_msg) = run_parsed(file.to_string(), parsed, env) {
-        if let Some(msg) = exit_msg {
-            println!("{}", msg);
-        }
+/// A short description of the special form `name`.
+pub fn special_form_doc(name: &str) -> Option<&'static str> {
+    match name {
+        "define" => Some("(define name value) or (define (name params...) body...) binds a name in the env."),
+        "lambda" => Some("(lambda (params...) body...) makes a function."),
+        "quote" => Some("(quote expr) or 'expr returns expr without evaluating it."),
+        "set!" => Some("(set! name value) changes the value of a bound name."),
+        "guard" => Some("(guard (var [case body...] ...) body...) evaluates body, handling conditions it signals with the first clause whose case holds."),
+        "module" => Some("(module name (export names...)) names a module and the definitions it exports; see require."),
+        _ => None,
     }
 }
 
-pub fn run_input(input: String, env: &mut Env) -> Inhibit {
-    let parsed = parse(&input);
-    run_parsed(input, parsed, env)
-}
-
-fn run_parsed(original: String, parsed: Result<Vec<Expr>, String>, env: &mut Env) -> Inhibit {
-    match parsed {
-        Ok(exprs) => {
-            for expr_idx in 0..exprs.len() {
-                let evaluated = exprs[expr_idx].eval(env);
-                match evaluated {
-                    Ok(Some(Object::Exit(s))) => {
-                        return Inhibit::Stop(s);
-                    },
-                    Ok(Some(r)) => {
-                        if expr_idx == exprs.len() - 1 {
-                            println!("{:?}", r);
-                        }
-                    },
-                    Ok(None) => {},
-                    Err(e) => println!("Eval of input: {nl}{nl}{input}{nl} failed with error: {nl}{nl} {e} {nl}", nl = NEWL, input = original, e = e)
-                }
-            }
-        },
-        Err(e) => println!("Parsing of input: {nl}{nl}{input}{nl} failed with error: {nl}{nl} {e} {nl}", nl = NEWL, input = original, e = e)
+/// Evaluates a top-level form with the env's chosen evaluator, or the
+/// tree-walker while a debugger is attached.
+pub fn eval_form(expr: &Expr, env: &mut Env) -> Result<Option<Object>, Condition> {
+    match env.evaluator {
+        Evaluator::Bytecode if env.debugging.is_none() => vm::eval(expr, env),
+        _ => expr.eval(env),
     }
-    return Inhibit::Continue;
 }
 
 pub trait Eval {
-    fn eval(&self, _: &mut Env) -> Result<Option<Object>, String>;
+    fn eval(&self, _: &mut Env) -> Result<Option<Object>, Condition>;
 }
 
 impl Eval for Vec<Expr> {
-    fn eval(&self, env: &mut Env) -> Result<Option<Object>, String> {
+    fn eval(&self, env: &mut Env) -> Result<Option<Object>, Condition> {
         if self.len() != 0 {
             if self.len() != 1 {
                 for i in 0..self.len() - 1 {
@@ -59,60 +44,28 @@ impl Eval for Vec<Expr> {
             }
             self[self.len() - 1].eval(env)
         } else {
-            Err("Cannot eval empty list of exprs".to_string())
+            Err(Condition::new("syntax-error", "Cannot eval empty list of exprs".to_string()))
         }
     }
 }
 
 impl Eval for Expr {
-    fn eval(&self, env: &mut Env) -> Result<Option<Object>, String> {
+    fn eval(&self, env: &mut Env) -> Result<Option<Object>, Condition> {
+        try!(env.step());
         match *self {
-            Expr::Exprs(ref exprs) => {
-                let (orig_head, tail): (&Expr, &[Expr]) = exprs.split_first().unwrap();
-                let head;
-                if let Expr::Exprs(_) = *orig_head {
-                    head = Expr::Expr(try!((*orig_head).eval(env)).unwrap());
-                } else {
-                    head = (*orig_head).clone();
-                }
-                if let Expr::Expr(Object::Symbol(ref function_name)) = head {
-                    let args = tail.to_vec();
-                    if function_name == "define" {
-                        let (first, rest) = args.split_first().unwrap();
-                        match *first {
-                            Expr::Expr(Object::Symbol(ref var)) => {
-                                define_variable(var, rest, env)
-                            },
-                            Expr::Expr(ref tried_ident) => {
-                                Err(format!("Invalid variable identifier \"{:?}\"", tried_ident))
-                            },
-                            Expr::Exprs(ref fndef) => {
-                                define_function(fndef, rest, env)
-                            },
-                        }
-                    } else if function_name == "lambda" {
-                        let (first, rest) = args.split_first().unwrap();
-                        if let Expr::Exprs(ref fndef) = *first {
-                            Ok(Some(Object::Function(try!(Function::from_exprs(fndef, rest)))))
-                        } else {
-                            Err("Invalid lambda function".to_string())
-                        }
-                    } else {
-                        eval_function_named(function_name, &args, env)
-                    }
-                } else if let Expr::Expr(Object::Function(ref function)) = head {
-                    eval_function((*function).clone(), tail, env)
-                } else {
-                    Err(format!("Invalid function name {:?}", head))
-                }
+            Expr::Exprs(ref exprs, _) => {
+                // nested forms recurse on the Rust stack as calls do
+                try!(env.enter_form());
+                let result = eval_list(exprs, self, env);
+                env.exit_form();
+                result
             }
             Expr::Expr(ref object) => {
                 match *object {
                     Object::Symbol(ref varname) => {
-                        if env.var_exists(varname) {
-                            Ok(Some(env.get_variable(varname)))
-                        } else {
-                            Err(format!("No such variable {}", varname))
+                        match env.lookup(varname) {
+                            Some(value) => Ok(Some(value)),
+                            None => Err(Condition::new("unbound-variable", format!("No such variable {}", varname))),
                         }
                     },
                     _ => Ok(Some(object.clone()))
@@ -122,43 +75,174 @@ impl Eval for Expr {
     }
 }
 
-fn define_variable(var: &str, args: &[Expr], env: &mut Env) -> Result<Option<Object>, String> {
-    match args.last().unwrap().clone().eval(env) {
+// Evaluates `form`, whose parts are `exprs`: a special form or a call.
+fn eval_list(exprs: &[Expr], form: &Expr, env: &mut Env) -> Result<Option<Object>, Condition> {
+    let (orig_head, tail): (&Expr, &[Expr]) = match exprs.split_first() {
+        Some(split) => split,
+        None => return Err(Condition::new("syntax-error", "Cannot evaluate an empty form ()".to_string())),
+    };
+    let head;
+    if let Expr::Exprs(..) = *orig_head {
+        head = match try!((*orig_head).eval(env)) {
+            Some(function) => Expr::Expr(function),
+            None => return Err(Condition::new("type-error", "Function position has no value".to_string())),
+        };
+    } else {
+        head = (*orig_head).clone();
+    }
+    if let Expr::Expr(Object::Symbol(ref function_name)) = head {
+        let args = tail;
+        if function_name == "define" {
+            let (first, rest) = match args.split_first() {
+                Some(split) => split,
+                None => return Err(Condition::new("syntax-error", "define requires a name".to_string())),
+            };
+            match *first {
+                Expr::Expr(Object::Symbol(ref var)) => {
+                    define_variable(var, rest, env)
+                },
+                Expr::Expr(ref tried_ident) => {
+                    Err(Condition::new("syntax-error", format!("Invalid variable identifier \"{:?}\"", tried_ident)))
+                },
+                Expr::Exprs(ref fndef, _) => {
+                    define_function(fndef, rest, env)
+                },
+            }
+        } else if function_name == "lambda" {
+            if let Some((&Expr::Exprs(ref fndef, _), rest)) = args.split_first() {
+                let function = try!(Function::from_exprs(fndef, rest));
+                Ok(Some(Object::Function(function.in_namespace(env.namespace.clone()))))
+            } else {
+                Err(Condition::new("syntax-error", "Invalid lambda function".to_string()))
+            }
+        } else if function_name == "quote" {
+            match args.len() {
+                1 => Ok(Some(args[0].to_object())),
+                _ => Err(Condition::new("syntax-error", "quote takes exactly one form".to_string())),
+            }
+        } else if function_name == "set!" {
+            eval_set(args, env)
+        } else if function_name == "module" {
+            // only meaningful as the first form of a required file
+            Ok(None)
+        } else if function_name == "guard" {
+            eval_guard(args, env)
+        } else {
+            eval_function_named(function_name, args, form, env)
+        }
+    } else if let Expr::Expr(Object::Function(ref function)) = head {
+        eval_function((*function).clone(), tail, form, env)
+    } else {
+        Err(Condition::new("type-error", format!("Invalid function name {:?}", head)))
+    }
+}
+
+fn define_variable(var: &str, args: &[Expr], env: &mut Env) -> Result<Option<Object>, Condition> {
+    let value = match args.last() {
+        Some(value) => value,
+        None => return Err(Condition::new("syntax-error", format!("define of {} requires a value", var))),
+    };
+    match value.eval(env) {
         Ok(Some(value)) => {
             env.add_variable(var.to_string(), value);
             return Ok(None);
         },
-        Ok(None) => return Err("Cannot set variable to nonetype".to_string()),
+        Ok(None) => return Err(Condition::new("type-error", "Cannot set variable to nonetype".to_string())),
         Err(e) => return Err(e),
     };
 }
 
-fn define_function(declaration: &[Expr], args: &[Expr], env: &mut Env) -> Result<Option<Object>, String> {
-    if args.len() > 1 {
-        return Err(format!("Function body of function {:?} too short", declaration[0]));
+fn define_function(declaration: &[Expr], args: &[Expr], env: &mut Env) -> Result<Option<Object>, Condition> {
+    if args.is_empty() {
+        return Err(Condition::new("syntax-error", format!("Function body of function {:?} too short", declaration)));
     } else {
-        if let Expr::Expr(Object::Symbol(ref fn_name)) = declaration[0] {
+        if let Some(&Expr::Expr(Object::Symbol(ref fn_name))) = declaration.first() {
             let fnargs = &declaration[1..declaration.len()];
             let body = args;
-            let function = try!(Function::from_exprs(fnargs, body));
+            let function = try!(Function::from_exprs(fnargs, body)).in_namespace(env.namespace.clone());
             env.add_variable(fn_name.to_string(), Object::Function(function));
             return Ok(None);
         } else {
-            return Err(format!("Invalid function identifier {:?}", declaration[0]));
+            return Err(Condition::new("syntax-error", format!("Invalid function identifier {:?}", declaration.first())));
         }
     }
 }
 
-fn eval_function_named(function_name: &str, args: &[Expr], env: &mut Env) -> Result<Option<Object>, String> {
-    let function = match_first_function(function_name, env.variables());
-    if function.is_ok() {
-        eval_function(function.ok().unwrap(), args, env)
-    } else {
-        Err(function.err().unwrap())
+// (set! var value)
+// Changes an existing binding; params are substituted into function bodies
+// before they run, so only top-level variables can be set.
+fn eval_set(args: &[Expr], env: &mut Env) -> Result<Option<Object>, Condition> {
+    let (var, value) = match args {
+        &[Expr::Expr(Object::Symbol(ref var)), ref value] => (var, value),
+        _ => return Err(Condition::new("syntax-error", format!("set! requires a variable name and a value, not {:?}", args))),
+    };
+    match try!(value.eval(env)) {
+        Some(value) => {
+            try!(env.set_variable(var.to_string(), value));
+            Ok(None)
+        }
+        None => Err(Condition::new("type-error", "Cannot set variable to nonetype".to_string())),
+    }
+}
+
+// (guard (var [case body...] ...) body...)
+// Evaluates body; if it signals a condition, the condition is bound to var and
+// the first clause whose case is true (or else) handles it. With no matching
+// clause the condition is re-raised.
+fn eval_guard(args: &[Expr], env: &mut Env) -> Result<Option<Object>, Condition> {
+    let (spec, body) = match args.split_first() {
+        Some((&Expr::Exprs(ref spec, _), body)) if !body.is_empty() => (spec, body),
+        _ => return Err(Condition::new("syntax-error", "guard requires a (var clauses...) spec and a body".to_string())),
+    };
+    let (var, clauses) = match spec.split_first() {
+        Some((&Expr::Expr(ref var @ Object::Symbol(_)), clauses)) => (var, clauses),
+        _ => return Err(Condition::new("syntax-error", "Invalid guard variable".to_string())),
+    };
+    let condition = match body.to_vec().eval(env) {
+        Err(condition) if condition.is_catchable() => condition,
+        result => return result,
+    };
+    let condition_object = Object::Condition(condition.clone());
+    let mut var_mapping = HashMap::new();
+    var_mapping.insert(var, &condition_object);
+    for clause in clauses {
+        if let Expr::Expr(Object::ConditionalCase(ref case, ref handler)) = clause.replace_all(&var_mapping) {
+            let matched = if let Expr::Expr(Object::Symbol(ref name)) = **case {
+                name == "else"
+            } else {
+                match try!(case.eval(env)) {
+                    Some(Object::Boolean(boolean)) => boolean.into(),
+                    _ => return Err(Condition::new("type-error", format!("Case {:?} does not return a boolean", case))),
+                }
+            };
+            if matched {
+                return handler.eval(env);
+            }
+        } else {
+            return Err(Condition::new("syntax-error", format!("Invalid guard clause {:?}", clause)));
+        }
     }
+    Err(condition)
+}
+
+fn eval_function_named(function_name: &str, args: &[Expr], call: &Expr, env: &mut Env) -> Result<Option<Object>, Condition> {
+    let function = match env.get_function(function_name) {
+        Some(function) => function,
+        None => return Err(Condition::new("unbound-variable", format!("No such function {:?}", function_name))),
+    };
+    eval_function(function, args, call, env)
+}
+
+fn eval_function(function: Function, args: &[Expr], call: &Expr, env: &mut Env) -> Result<Option<Object>, Condition> {
+    debug::enter(call, env);
+    let result = eval_and_apply(function, args, call, env);
+    debug::exit(env);
+    result
 }
 
-fn eval_function(function: Function, args: &[Expr], env: &mut Env) -> Result<Option<Object>, String> {
+// Evaluates the args and applies the function to them; errors from applying it
+// record `call` in their backtrace.
+fn eval_and_apply(function: Function, args: &[Expr], call: &Expr, env: &mut Env) -> Result<Option<Object>, Condition> {
     let mut evaled_args: Vec<Object> = Vec::new();
     for expr in args.iter() {
         let evalresult = expr.eval(env);
@@ -169,52 +253,66 @@ fn eval_function(function: Function, args: &[Expr], env: &mut Env) -> Result<Opt
             Err(_) => return evalresult,
         }
     }
+    let result = if env.debugging.is_some() {
+        debug::apply(function, evaled_args, call, env)
+    } else {
+        apply_function(function, evaled_args, env)
+    };
+    result.map_err(|condition| condition.called_from(call))
+}
+
+pub fn apply_function(function: Function, evaled_args: Vec<Object>, env: &mut Env) -> Result<Option<Object>, Condition> {
     match *function.procedure {
+        LispFn::UserDef(..) if env.evaluator == Evaluator::Bytecode && env.debugging.is_none() => {
+            vm::apply(function.clone(), evaled_args, env)
+        }
         LispFn::Builtin(ref innerfn) => {
-            let evaluated = (innerfn.inner())(evaled_args, env);
-            match evaluated {
-                Ok(Some(r)) => Ok(Some(r)),
-                Ok(None) => Ok(None),
-                Err(e) => Err(e)
+            let evaluated = try!(innerfn.call(evaled_args, env));
+            if let Some(ref result) = evaluated {
+                try!(env.allocate(result));
             }
+            Ok(evaluated)
         }
-        LispFn::UserDef(ref vars, ref body) => {
+        LispFn::UserDef(ref vars, ref body, _) => {
             if evaled_args.len() != vars.len() {
-                Err(format!("Function {:?} run with {} args; should be run with {} args", function, evaled_args.len(), vars.len()))
+                Err(Condition::new("arity-error", format!("Function {:?} run with {} args; should be run with {} args", function, evaled_args.len(), vars.len())))
             } else {
-                let mut var_mappings = HashMap::new();
-                for (var, var_mapping) in vars.iter().zip(evaled_args.iter()) {
-                    var_mappings.insert(var, var_mapping);
-                }
-                let newbody = body
-                    .iter()
-                    .map(|ref expr| expr.replace_all(&var_mappings))
-                    .collect::<Vec<_>>();
-                if newbody.len() > 1 {
-                    let (final_expr, leading_exprs) = body.split_last().unwrap();
-                    for expr in leading_exprs {
-                        try!(expr.replace_all(&var_mappings).eval(env));
-                    }
-                    final_expr.replace_all(&var_mappings).eval(env)
-                } else {
-                    newbody.first()
-                        .unwrap()
-                        .replace_all(&var_mappings)
-                        .eval(env)
-                }
+                try!(env.enter_call());
+                let caller_forms = env.enter_body();
+                let caller_namespace = mem::replace(&mut env.namespace, function.namespace.clone());
+                let result = eval_user_function(vars, body, &evaled_args, env);
+                env.namespace = caller_namespace;
+                env.exit_body(caller_forms);
+                env.exit_call();
+                result
+            }
+        }
+        LispFn::Continuation(id) => {
+            if evaled_args.len() > 1 {
+                Err(Condition::new("arity-error", "Continuations take at most one arg".to_string()))
+            } else {
+                Err(Condition::escape(id, evaled_args.into_iter().next()))
             }
         }
     }
 }
 
-fn match_first_function<'a>(function_name: &str, vars: HashMap<String, Object>) -> Result<Function, String> {
-    for (var, object) in vars.iter() {
-        if var == function_name {
-            if let &Object::Function(ref func) = object {
-                return Ok((*func).clone());
+fn eval_user_function(vars: &[Object], body: &[Expr], evaled_args: &[Object], env: &mut Env) -> Result<Option<Object>, Condition> {
+    let mut var_mappings = HashMap::new();
+    for (var, var_mapping) in vars.iter().zip(evaled_args.iter()) {
+        var_mappings.insert(var, var_mapping);
+    }
+    let newbody = body
+        .iter()
+        .map(|ref expr| expr.replace_all(&var_mappings))
+        .collect::<Vec<_>>();
+    match newbody.split_last() {
+        Some((final_expr, leading_exprs)) => {
+            for expr in leading_exprs {
+                try!(expr.eval(env));
             }
+            final_expr.eval(env)
         }
-        
+        None => Err(Condition::new("syntax-error", "Function body is empty".to_string())),
     }
-    Err(format!("No such function {:?}", function_name))
 }
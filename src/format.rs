@@ -0,0 +1,265 @@
+//! Formatting source in one consistent layout.
+//!
+//! A form that fits in the width and holds no comments goes on one line.
+//! Otherwise `define`, `lambda`, `let`, `guard` and `module` keep their first
+//! arg beside them and indent their body by two spaces, other calls line their
+//! args up under the first, and lists of data line up under the first item.
+//! Args that are all atoms are packed onto as few lines as fit.
+//! Comments are kept, as is a single blank line wherever there was at least
+//! one.
+
+use syntax::{Node, NodeKind, SyntaxError, read};
+
+pub const DEFAULT_WIDTH: usize = 80;
+
+// forms whose body is indented, and how many args stay on the first line
+const BODY_FORMS: [(&'static str, usize); 5] = [("define", 1), ("lambda", 1), ("let", 1), ("guard", 1), ("module", 1)];
+const BODY_INDENT: usize = 2;
+
+/// Formats `source` to fit in `width` columns where it can. Source that
+/// cannot be read is left alone and the first error is returned.
+pub fn format(source: &str, width: usize) -> Result<String, SyntaxError> {
+    let (nodes, errors) = read(source);
+    if let Some(error) = errors.into_iter().next() {
+        return Err(error);
+    }
+    let formatter = Formatter {
+        source: source,
+        width: width,
+    };
+    let mut out = String::new();
+    formatter.sequence(&nodes, 0, None, 0, 0, &mut out);
+    if !out.is_empty() {
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+struct Formatter<'a> {
+    source: &'a str,
+    width: usize,
+}
+
+impl<'a> Formatter<'a> {
+    // The node on one line, if it has no comments.
+    fn flat(&self, node: &Node) -> Option<String> {
+        match node.kind {
+            NodeKind::Atom | NodeKind::String => Some(node.text(self.source).to_string()),
+            NodeKind::Comment => None,
+            NodeKind::Quote(ref comments, ref quoted) if comments.is_empty() => self.flat(quoted).map(|quoted| format!("'{}", quoted)),
+            NodeKind::Quote(..) => None,
+            NodeKind::List(ref children) | NodeKind::Case(ref children) => {
+                let mut parts = Vec::new();
+                for child in children {
+                    match self.flat(child) {
+                        Some(part) => parts.push(part),
+                        None => return None,
+                    }
+                }
+                let (open, close) = brackets(node);
+                Some(format!("{}{}{}", open, parts.join(" "), close))
+            }
+        }
+    }
+
+    // Whether `text` fits on the line from `column`, leaving room for the
+    // `closing` brackets that will follow it.
+    fn fits(&self, text: &str, column: usize, closing: usize) -> bool {
+        !text.contains('\n') && column + text.chars().count() + closing <= self.width
+    }
+
+    // The node laid out starting at `column`.
+    fn node(&self, node: &Node, column: usize, closing: usize) -> String {
+        if let Some(flat) = self.flat(node) {
+            if self.fits(&flat, column, closing) {
+                return flat;
+            }
+        }
+        match node.kind {
+            NodeKind::Quote(ref comments, ref quoted) => {
+                // comments after the quote each end a line, so the form goes
+                // on the line below them
+                let mut out = "'".to_string();
+                for comment in comments {
+                    out.push_str(comment.text(self.source));
+                    out.push('\n');
+                    out.push_str(&" ".repeat(column + 1));
+                }
+                out.push_str(&self.node(quoted, column + 1, closing));
+                out
+            }
+            NodeKind::List(ref children) | NodeKind::Case(ref children) => self.list(node, children, column, closing),
+            _ => node.text(self.source).to_string(),
+        }
+    }
+
+    fn list(&self, node: &Node, children: &[Node], column: usize, closing: usize) -> String {
+        let (open, close) = brackets(node);
+        if children.is_empty() {
+            return format!("{}{}", open, close);
+        }
+        let mut out = open.to_string();
+        // how many children go on the first line, and where the rest line up
+        let head = match children.first() {
+            Some(first) if first.kind != NodeKind::Comment => first.symbol(self.source),
+            _ => None,
+        };
+        let body_args = head.and_then(|head| BODY_FORMS.iter().find(|form| form.0 == head)).map(|form| form.1);
+        let (first_line, indent) = match (head, body_args) {
+            (Some(_), Some(args)) => (1 + args, column + BODY_INDENT),
+            (Some(head), None) => {
+                let aligned = column + 1 + head.chars().count() + 1;
+                // a long head leaves too little room to line args up after it
+                if aligned + BODY_INDENT * 4 <= self.width {
+                    (2, aligned)
+                } else {
+                    (1, column + 1)
+                }
+            }
+            _ => (1, column + 1),
+        };
+        let first_line = children.iter().take(first_line).take_while(|child| child.kind != NodeKind::Comment).count().max(1);
+        let first_line = first_line.min(children.len());
+        for (i, child) in children[..first_line].iter().enumerate() {
+            if i > 0 {
+                out.push(' ');
+            }
+            let child_column = end_column(column, &out);
+            let child_closing = if i == children.len() - 1 { closing + 1 } else { 0 };
+            out.push_str(&self.node(child, child_column, child_closing));
+        }
+        let rest = &children[first_line..];
+        let atoms = rest.iter().all(|child| child.kind == NodeKind::Atom || child.kind == NodeKind::String);
+        if body_args.is_none() && atoms {
+            self.fill(rest, indent, column, closing + 1, &mut out);
+        } else {
+            self.sequence(rest, indent, children.get(first_line - 1), column, closing + 1, &mut out);
+        }
+        if children.last().map(|last| last.kind == NodeKind::Comment).unwrap_or(false) {
+            out.push('\n');
+            out.push_str(&" ".repeat(indent));
+        }
+        out.push_str(close);
+        out
+    }
+
+    // Lays out atoms as many to a line as fit, lining lines up at `indent`.
+    fn fill(&self, nodes: &[Node], indent: usize, column: usize, closing: usize, out: &mut String) {
+        for (i, node) in nodes.iter().enumerate() {
+            let text = node.text(self.source);
+            let node_closing = if i == nodes.len() - 1 { closing } else { 0 };
+            if self.fits(&format!(" {}", text), end_column(column, out), node_closing) {
+                out.push(' ');
+            } else {
+                out.push('\n');
+                out.push_str(&" ".repeat(indent));
+            }
+            out.push_str(text);
+        }
+    }
+
+    // Lays out `nodes` one per line at `indent`, after `previous` if there is
+    // one already in `out`, which starts at `column`; `closing` brackets follow
+    // the last. Comments that were on the same line as the node before them
+    // stay there.
+    fn sequence(&self,
+                nodes: &[Node],
+                indent: usize,
+                previous: Option<&Node>,
+                column: usize,
+                closing: usize,
+                out: &mut String) {
+        let mut previous = previous;
+        for (i, node) in nodes.iter().enumerate() {
+            let gap = previous.map(|previous| &self.source[previous.end..node.start]);
+            match gap {
+                Some(gap) if node.kind == NodeKind::Comment && !gap.contains('\n') => out.push(' '),
+                Some(gap) => {
+                    if gap.matches('\n').count() > 1 {
+                        out.push('\n');
+                    }
+                    out.push('\n');
+                    out.push_str(&" ".repeat(indent));
+                }
+                None => {}
+            }
+            let node_column = end_column(column, out);
+            let node_closing = if i == nodes.len() - 1 { closing } else { 0 };
+            out.push_str(&self.node(node, node_column, node_closing));
+            previous = Some(node);
+        }
+    }
+}
+
+fn brackets(node: &Node) -> (&'static str, &'static str) {
+    match node.kind {
+        NodeKind::Case(_) => ("[", "]"),
+        _ => ("(", ")"),
+    }
+}
+
+// The column reached by writing `text` from `column`.
+fn end_column(column: usize, text: &str) -> usize {
+    match text.rfind('\n') {
+        Some(newline) => text[newline + 1..].chars().count(),
+        None => column + text.chars().count(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &'static str = "(define (fact n) (cond [(= n 0) 1] [else (* n (fact (- n 1)))]))
+
+
+; a comment
+(print   \"a  b\" (list 1 2 3) '(x y))
+(define (long-function-name first-argument second-argument) (some-function first-argument second-argument (another-function first-argument)))
+(list 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16 17 18 19 20 21 22 23 24 25 26 27 28 29 30)
+(f x ; trailing
+ y)
+";
+
+    #[test]
+    fn layout() {
+        assert_eq!(format(SOURCE, DEFAULT_WIDTH).unwrap(),
+                   "(define (fact n) (cond [(= n 0) 1] [else (* n (fact (- n 1)))]))
+
+; a comment
+(print \"a  b\" (list 1 2 3) '(x y))
+(define (long-function-name first-argument second-argument)
+  (some-function first-argument
+                 second-argument
+                 (another-function first-argument)))
+(list 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16 17 18 19 20 21 22 23 24 25 26 27 28
+      29 30)
+(f x ; trailing
+   y)
+");
+        assert_eq!(format("", DEFAULT_WIDTH).unwrap(), "");
+    }
+
+    #[test]
+    fn idempotent() {
+        for &width in [DEFAULT_WIDTH, 40, 30, 10].iter() {
+            let formatted = format(SOURCE, width).unwrap();
+            assert_eq!(format(&formatted, width).unwrap(), formatted, "at width {}", width);
+        }
+    }
+
+    #[test]
+    fn quoted_comments() {
+        let formatted = format("(f '   ; why\n; and more\n(a  b))", DEFAULT_WIDTH).unwrap();
+        assert_eq!(formatted, "(f '; why\n    ; and more\n    (a b))\n");
+        assert_eq!(format(&formatted, DEFAULT_WIDTH).unwrap(), formatted);
+    }
+
+    #[test]
+    fn unreadable() {
+        assert_eq!(format("(f x", DEFAULT_WIDTH).unwrap_err().start, 0);
+        let deep = format!("(f {}x{} y)", "(".repeat(100000), ")".repeat(100000));
+        let error = format(&deep, DEFAULT_WIDTH).unwrap_err();
+        assert_eq!((error.message.as_str(), error.start), ("Nested more than 512 deep", 3 + 511));
+    }
+}
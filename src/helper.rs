@@ -0,0 +1,135 @@
+use rlisp::Object;
+use rlisp::eval::SPECIAL_FORMS;
+use rlisp::lexer::{Token, TokenKind, lex};
+use rlisp::parser::atomize;
+use rustyline::Helper;
+use rustyline::completion::Completer;
+use rustyline::Context;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use std::borrow::Cow;
+use style;
+
+/// Line editor support for the repl: completes the symbol under the cursor
+/// from the special forms and the names bound in the repl's env, and colours
+/// the line, marking the bracket matching the one at the cursor.
+pub struct LispHelper {
+    names: Vec<String>,
+}
+
+impl LispHelper {
+    pub fn new() -> LispHelper {
+        LispHelper {
+            names: Vec::new(),
+        }
+    }
+    /// Replaces the bound names to complete, which change as input is evaluated.
+    pub fn set_names<I: Iterator<Item = String>>(&mut self, names: I) {
+        self.names = names.chain(SPECIAL_FORMS.iter().map(|form| form.to_string())).collect();
+        self.names.sort();
+        self.names.dedup();
+    }
+}
+
+impl Completer for LispHelper {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _: &Context) -> ::rustyline::Result<(usize, Vec<String>)> {
+        let start = match lex(&line[..pos]).last() {
+            Some(token) if token.kind == TokenKind::Atom => token.start,
+            _ => pos,
+        };
+        let prefix = &line[start..pos];
+        Ok((start, self.names.iter().filter(|name| name.starts_with(prefix)).cloned().collect()))
+    }
+}
+
+impl Hinter for LispHelper {
+    type Hint = String;
+}
+
+impl Highlighter for LispHelper {
+    fn highlight<'l>(&self, line: &'l str, pos: usize) -> Cow<'l, str> {
+        if !style::enabled() {
+            return Cow::Borrowed(line);
+        }
+        let tokens = lex(line);
+        let matching = matching_brackets(&tokens, pos);
+        let mut highlighted = String::with_capacity(line.len() * 2);
+        for (i, token) in tokens.iter().enumerate() {
+            let text = token.text(line);
+            let colour = match token.kind {
+                _ if matching.contains(&i) => Some(style::MATCHING_BRACKET),
+                TokenKind::String | TokenKind::UnterminatedString => Some(style::STRING),
+                TokenKind::Comment => Some(style::COMMENT),
+                TokenKind::Quote => Some(style::SPECIAL_FORM),
+                TokenKind::Atom if SPECIAL_FORMS.contains(&text) => Some(style::SPECIAL_FORM),
+                TokenKind::Atom => {
+                    match atomize(text.to_string()) {
+                        Object::Symbol(_) => Some(style::SYMBOL),
+                        _ => Some(style::NUMBER),
+                    }
+                }
+                _ => None,
+            };
+            match colour {
+                Some(colour) => highlighted.push_str(&style::paint(colour, text)),
+                None => highlighted.push_str(text),
+            }
+        }
+        Cow::Owned(highlighted)
+    }
+
+    // the bracket at the cursor changes as it moves
+    fn highlight_char(&self, _: &str, _: usize, _: bool) -> bool {
+        style::enabled()
+    }
+}
+
+// The indices of the bracket at or just before `pos` and the one it pairs
+// with, if both are on the line.
+fn matching_brackets(tokens: &[Token], pos: usize) -> Vec<usize> {
+    let at_cursor = tokens.iter()
+        .position(|token| token.start == pos && is_bracket(token))
+        .or_else(|| tokens.iter().position(|token| token.end == pos && is_bracket(token)));
+    let i = match at_cursor {
+        Some(i) => i,
+        None => return Vec::new(),
+    };
+    let opening = match tokens[i].kind {
+        TokenKind::OpenParen | TokenKind::OpenBracket => true,
+        _ => false,
+    };
+    let mut depth = 0;
+    let others: Box<dyn Iterator<Item = usize>> = if opening {
+        Box::new(i + 1..tokens.len())
+    } else {
+        Box::new((0..i).rev())
+    };
+    for j in others {
+        match tokens[j].kind {
+            TokenKind::OpenParen | TokenKind::OpenBracket if opening => depth += 1,
+            TokenKind::CloseParen | TokenKind::CloseBracket if !opening => depth += 1,
+            TokenKind::OpenParen | TokenKind::OpenBracket | TokenKind::CloseParen | TokenKind::CloseBracket => {
+                if depth == 0 {
+                    return vec![i, j];
+                }
+                depth -= 1;
+            }
+            _ => {}
+        }
+    }
+    Vec::new()
+}
+
+fn is_bracket(token: &Token) -> bool {
+    match token.kind {
+        TokenKind::OpenParen | TokenKind::OpenBracket | TokenKind::CloseParen | TokenKind::CloseBracket => true,
+        _ => false,
+    }
+}
+
+impl Validator for LispHelper {}
+
+impl Helper for LispHelper {}
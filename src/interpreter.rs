@@ -0,0 +1,149 @@
+use convert::FromLisp;
+use data::*;
+use debug::{Debugging, Pause, Step};
+use eval::{apply_function, eval_form};
+use parser::{parse, read_file};
+use std::error;
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Why a call into the interpreter failed.
+#[derive(Debug, Clone)]
+pub enum Error {
+    Parse(String),
+    Eval(Condition),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            Error::Parse(ref e) => write!(f, "parse error: {}", e),
+            Error::Eval(ref condition) => write!(f, "{}", condition),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        None
+    }
+}
+
+impl From<Condition> for Error {
+    fn from(condition: Condition) -> Error {
+        Error::Eval(condition)
+    }
+}
+
+/// An rlisp environment for host programs to evaluate code in.
+///
+/// Evaluation results are returned rather than printed; `None` is the result of
+/// forms with no value such as `define`. A form that calls `exit` stops
+/// evaluation and is returned as `Object::Exit`.
+///
+/// Calls may nest `DEFAULT_MAX_DEPTH` deep. The tree-walker, and the debugger
+/// which uses it, take about 30KB of stack per call in a debug build and 6KB in
+/// a release build, so evaluate on a thread with a 32MB stack (8MB in release)
+/// or lower `env_mut().limits.max_depth` to fit the one you have.
+pub struct Interpreter {
+    env: Env,
+}
+
+impl Interpreter {
+    pub fn new() -> Interpreter {
+        Interpreter::with_env(Env::new())
+    }
+    pub fn with_env(env: Env) -> Interpreter {
+        Interpreter {
+            env: env,
+        }
+    }
+    pub fn env(&self) -> &Env {
+        &self.env
+    }
+    pub fn env_mut(&mut self) -> &mut Env {
+        &mut self.env
+    }
+    /// Evaluates every form in `input`, returning the value of the last one.
+    pub fn eval_str(&mut self, input: &str) -> Result<Option<Object>, Error> {
+        let exprs = try!(parse(&input.to_string()).map_err(Error::Parse));
+        self.eval_exprs(&exprs)
+    }
+    /// Evaluates every form in the file at `path`. A file that cannot be read
+    /// or parsed fails with an io-error or syntax-error condition naming it.
+    pub fn eval_file(&mut self, path: &str) -> Result<Option<Object>, Error> {
+        let exprs = try!(read_file(Path::new(path)));
+        self.eval_exprs(&exprs)
+    }
+    /// Evaluates a single parsed top-level form; `Env::limits` apply to each
+    /// form separately.
+    pub fn eval_expr(&mut self, expr: &Expr) -> Result<Option<Object>, Error> {
+        self.env.start_evaluation();
+        eval_form(expr, &mut self.env).map_err(Error::Eval)
+    }
+    /// Binds `name` at top level, replacing any existing binding.
+    pub fn define(&mut self, name: &str, value: Object) {
+        self.env.variables.insert(name.to_string(), value);
+    }
+    /// Attaches a debugger, called whenever evaluation pauses; see `debug`.
+    pub fn attach_debugger<F>(&mut self, debugger: F)
+        where F: Fn(&Pause, &mut Env) -> Step + Send + Sync + 'static
+    {
+        self.env.debugging = Some(Debugging::new(Arc::new(debugger)));
+    }
+    /// Exposes a Rust closure to lisp code as the builtin `name`.
+    pub fn define_fn<F>(&mut self, name: &str, arity: Arity, doc: &str, func: F)
+        where F: Fn(Vec<Object>, &mut Env) -> Result<Option<Object>, Condition> + Send + Sync + 'static
+    {
+        self.env.define_builtin(BuiltinFn::from_closure(name, func).with_arity(arity).with_doc(doc));
+    }
+    pub fn get(&self, name: &str) -> Option<Object> {
+        self.env.variables.get(name).cloned()
+    }
+    /// The value bound to `name`, converted to `T`.
+    pub fn get_as<T: FromLisp>(&self, name: &str) -> Result<T, Error> {
+        match self.get(name) {
+            Some(value) => convert(value, name),
+            None => Err(Error::Eval(Condition::new("unbound-variable", format!("No such variable {}", name)))),
+        }
+    }
+    pub fn call(&mut self, function_name: &str, args: Vec<Object>) -> Result<Option<Object>, Error> {
+        let function = match self.env.get_function(function_name) {
+            Some(function) => function,
+            None => return Err(Error::Eval(Condition::new("unbound-variable", format!("No such function {:?}", function_name)))),
+        };
+        self.env.start_evaluation();
+        apply_function(function, args, &mut self.env).map_err(Error::Eval)
+    }
+    /// Calls `function_name` and converts its result to `T`. No result
+    /// converts only to an `Option`, as `None`.
+    pub fn call_as<T: FromLisp>(&mut self, function_name: &str, args: Vec<Object>) -> Result<T, Error> {
+        let what = format!("the result of {}", function_name);
+        match try!(self.call(function_name, args)) {
+            Some(result) => convert(result, &what),
+            None => {
+                T::from_missing().ok_or(Error::Eval(Condition::new("type-error", format!("{} has no value", what))))
+            }
+        }
+    }
+
+    fn eval_exprs(&mut self, exprs: &[Expr]) -> Result<Option<Object>, Error> {
+        let mut result = None;
+        for expr in exprs {
+            result = try!(self.eval_expr(expr));
+            if let Some(Object::Exit(_)) = result {
+                break;
+            }
+        }
+        Ok(result)
+    }
+}
+
+// `value` as a `T`, or a type error saying that `what` is not one.
+fn convert<T: FromLisp>(value: Object, what: &str) -> Result<T, Error> {
+    match T::from_lisp(value.clone()) {
+        Some(converted) => Ok(converted),
+        None => Err(Error::Eval(Condition::with_irritants("type-error", format!("{} is not {}", what, T::type_name()), vec![value]))),
+    }
+}
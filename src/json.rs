@@ -0,0 +1,483 @@
+//! Reading and writing JSON text as lisp objects.
+//!
+//! Objects become hash tables with string keys, arrays become lists and null
+//! becomes `nil`. Numbers without a fraction or exponent that fit in an i64
+//! are read as integers. Arrays and objects may be nested `MAX_NESTING` deep.
+
+use data::*;
+use std::collections::HashMap;
+use std::fmt;
+
+/// How deeply arrays and objects may be nested in parsed text, so that
+/// parsing cannot overflow the stack.
+pub const MAX_NESTING: usize = 512;
+
+/// The widest indent `to_string` writes, as in JavaScript's `JSON.stringify`.
+pub const MAX_INDENT: usize = 10;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct JsonError {
+    pub offset: usize,
+    pub message: String,
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "{} at byte {}", self.message, self.offset)
+    }
+}
+
+pub fn parse(text: &str) -> Result<Object, JsonError> {
+    let mut parser = Parser {
+        text: text.as_bytes(),
+        pos: 0,
+        depth: 0,
+    };
+    let value = try!(parser.value());
+    parser.skip_whitespace();
+    if parser.pos != parser.text.len() {
+        return Err(parser.error("Unexpected trailing characters"));
+    }
+    Ok(value)
+}
+
+/// Writes `object` as JSON, indenting nested values by `indent` spaces if given.
+/// An indent wider than `MAX_INDENT` is an error.
+pub fn to_string(object: &Object, indent: Option<usize>) -> Result<String, String> {
+    if let Some(indent) = indent {
+        if indent > MAX_INDENT {
+            return Err(format!("Cannot indent by {} spaces, only up to {}", indent, MAX_INDENT));
+        }
+    }
+    let mut out = String::new();
+    try!(write_value(object, indent, 0, &mut out));
+    Ok(out)
+}
+
+struct Parser<'a> {
+    text: &'a [u8],
+    pos: usize,
+    // how many arrays and objects the parser is inside
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, message: &str) -> JsonError {
+        JsonError {
+            offset: self.pos,
+            message: message.to_string(),
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.pos < self.text.len() {
+            match self.text[self.pos] {
+                b' ' | b'\t' | b'\n' | b'\r' => self.pos += 1,
+                _ => break,
+            }
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.text.get(self.pos).cloned()
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), JsonError> {
+        if self.peek() == Some(byte) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.error(&format!("Expected '{}'", byte as char)))
+        }
+    }
+
+    fn literal(&mut self, word: &str, value: Object) -> Result<Object, JsonError> {
+        if self.text[self.pos..].starts_with(word.as_bytes()) {
+            self.pos += word.len();
+            Ok(value)
+        } else {
+            Err(self.error("Invalid literal"))
+        }
+    }
+
+    fn value(&mut self) -> Result<Object, JsonError> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(b'{') => self.nested(Parser::object),
+            Some(b'[') => self.nested(Parser::array),
+            Some(b'"') => self.string().map(Object::String),
+            Some(b't') => self.literal("true", true.into()),
+            Some(b'f') => self.literal("false", false.into()),
+            Some(b'n') => self.literal("null", Object::Nil),
+            Some(b'-') | Some(b'0'..=b'9') => self.number(),
+            Some(_) => Err(self.error("Unexpected character")),
+            None => Err(self.error("Unexpected end of input")),
+        }
+    }
+
+    fn nested(&mut self, parse: fn(&mut Parser<'a>) -> Result<Object, JsonError>) -> Result<Object, JsonError> {
+        if self.depth == MAX_NESTING {
+            return Err(self.error(&format!("Nested more than {} deep", MAX_NESTING)));
+        }
+        self.depth += 1;
+        let value = parse(self);
+        self.depth -= 1;
+        value
+    }
+
+    fn object(&mut self) -> Result<Object, JsonError> {
+        self.pos += 1;
+        let mut table = HashMap::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Ok(Object::HashTable(HashTable(table)));
+        }
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some(b'"') {
+                return Err(self.error("Expected a string key"));
+            }
+            let key = try!(self.string());
+            self.skip_whitespace();
+            try!(self.expect(b':'));
+            let value = try!(self.value());
+            table.insert(Object::String(key), value);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    return Ok(Object::HashTable(HashTable(table)));
+                }
+                _ => return Err(self.error("Expected ',' or '}'")),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Object, JsonError> {
+        self.pos += 1;
+        let mut elems = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return Ok(Object::List(Box::new(elems)));
+        }
+        loop {
+            elems.push(try!(self.value()));
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.pos += 1;
+                    return Ok(Object::List(Box::new(elems)));
+                }
+                _ => return Err(self.error("Expected ',' or ']'")),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, JsonError> {
+        self.pos += 1;
+        let mut bytes = Vec::new();
+        loop {
+            match self.peek() {
+                None => return Err(self.error("Unterminated string")),
+                Some(b'"') => {
+                    self.pos += 1;
+                    break;
+                }
+                Some(b'\\') => {
+                    self.pos += 1;
+                    let escaped = match self.peek() {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'b') => '\u{8}',
+                        Some(b'f') => '\u{c}',
+                        Some(b'n') => '\n',
+                        Some(b'r') => '\r',
+                        Some(b't') => '\t',
+                        Some(b'u') => try!(self.unicode_escape()),
+                        _ => return Err(self.error("Invalid escape")),
+                    };
+                    self.pos += 1;
+                    let mut buf = [0; 4];
+                    bytes.extend_from_slice(escaped.encode_utf8(&mut buf).as_bytes());
+                }
+                Some(byte) if byte < 0x20 => return Err(self.error("Control character in string")),
+                Some(byte) => {
+                    bytes.push(byte);
+                    self.pos += 1;
+                }
+            }
+        }
+        String::from_utf8(bytes).map_err(|_| self.error("Invalid UTF-8 in string"))
+    }
+
+    // Reads the hex digits of a \u escape, and a following low surrogate if
+    // the first is a high one. Leaves pos on the last hex digit.
+    fn unicode_escape(&mut self) -> Result<char, JsonError> {
+        let high = try!(self.hex4(self.pos + 1));
+        self.pos += 4;
+        let code = if high >= 0xD800 && high < 0xDC00 {
+            if !self.text[self.pos + 1..].starts_with(b"\\u") {
+                return Err(self.error("Unpaired surrogate in escape"));
+            }
+            let low = try!(self.hex4(self.pos + 3));
+            if low < 0xDC00 || low >= 0xE000 {
+                return Err(self.error("Invalid low surrogate in escape"));
+            }
+            self.pos += 6;
+            0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+        } else {
+            high
+        };
+        ::std::char::from_u32(code).ok_or(self.error("Invalid unicode escape"))
+    }
+
+    fn hex4(&self, start: usize) -> Result<u32, JsonError> {
+        let digits = match self.text.get(start..start + 4) {
+            Some(digits) => digits,
+            None => return Err(self.error("Truncated unicode escape")),
+        };
+        let mut code = 0;
+        for &digit in digits {
+            code = code * 16 + match (digit as char).to_digit(16) {
+                Some(d) => d,
+                None => return Err(self.error("Invalid unicode escape")),
+            };
+        }
+        Ok(code)
+    }
+
+    // A number as RFC 8259 has it: no leading zeros, and digits on both sides
+    // of a point and after an exponent.
+    fn number(&mut self) -> Result<Object, JsonError> {
+        let start = self.pos;
+        let invalid = JsonError {
+            offset: start,
+            message: "Invalid number".to_string(),
+        };
+        if self.peek() == Some(b'-') {
+            self.pos += 1;
+        }
+        if self.peek() == Some(b'0') {
+            self.pos += 1;
+            if self.digits() > 0 {
+                return Err(invalid);
+            }
+        } else if self.digits() == 0 {
+            return Err(invalid);
+        }
+        let mut is_float = false;
+        if self.peek() == Some(b'.') {
+            is_float = true;
+            self.pos += 1;
+            if self.digits() == 0 {
+                return Err(invalid);
+            }
+        }
+        if self.peek() == Some(b'e') || self.peek() == Some(b'E') {
+            is_float = true;
+            self.pos += 1;
+            if self.peek() == Some(b'+') || self.peek() == Some(b'-') {
+                self.pos += 1;
+            }
+            if self.digits() == 0 {
+                return Err(invalid);
+            }
+        }
+        let token = ::std::str::from_utf8(&self.text[start..self.pos]).unwrap();
+        if !is_float {
+            if let Ok(i) = token.parse::<i64>() {
+                return Ok(Object::Number(Number::Int(i)));
+            }
+        }
+        match token.parse::<f64>() {
+            Ok(f) => Ok(Object::Number(Number::Float(f, token.to_string()))),
+            Err(_) => Err(invalid),
+        }
+    }
+
+    // Skips any digits, returning how many there were.
+    fn digits(&mut self) -> usize {
+        let start = self.pos;
+        while let Some(b'0'..=b'9') = self.peek() {
+            self.pos += 1;
+        }
+        self.pos - start
+    }
+}
+
+fn write_value(object: &Object, indent: Option<usize>, depth: usize, out: &mut String) -> Result<(), String> {
+    match *object {
+        Object::Nil => out.push_str("null"),
+        Object::Boolean(Boolean::True) => out.push_str("true"),
+        Object::Boolean(Boolean::False) => out.push_str("false"),
+        Object::Number(Number::Int(i)) => out.push_str(&i.to_string()),
+        Object::Number(Number::Float(f, _)) => {
+            if !f.is_finite() {
+                return Err(format!("Cannot write {} as JSON", f));
+            }
+            let s = f.to_string();
+            out.push_str(&s);
+            if !s.contains('.') && !s.contains('e') {
+                out.push_str(".0");
+            }
+        }
+        Object::String(ref s) => write_string(s, out),
+        Object::List(ref elems) => {
+            out.push('[');
+            for (i, elem) in elems.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                try!(newline(indent, depth + 1, out));
+                try!(write_value(elem, indent, depth + 1, out));
+            }
+            if !elems.is_empty() {
+                try!(newline(indent, depth, out));
+            }
+            out.push(']');
+        }
+        Object::HashTable(ref table) => {
+            let mut entries = Vec::new();
+            for (key, value) in table.0.iter() {
+                match *key {
+                    Object::String(ref key) => entries.push((key, value)),
+                    ref other => return Err(format!("JSON object keys must be strings, not {:?}", other)),
+                }
+            }
+            entries.sort_by(|a, b| a.0.cmp(b.0));
+            out.push('{');
+            for (i, &(key, value)) in entries.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                try!(newline(indent, depth + 1, out));
+                write_string(key, out);
+                out.push(':');
+                if indent.is_some() {
+                    out.push(' ');
+                }
+                try!(write_value(value, indent, depth + 1, out));
+            }
+            if !entries.is_empty() {
+                try!(newline(indent, depth, out));
+            }
+            out.push('}');
+        }
+        ref other => return Err(format!("Cannot write {:?} as JSON", other)),
+    }
+    Ok(())
+}
+
+fn newline(indent: Option<usize>, depth: usize, out: &mut String) -> Result<(), String> {
+    if let Some(indent) = indent {
+        let width = try!(indent.checked_mul(depth).ok_or_else(|| "Too deeply nested to indent".to_string()));
+        out.push('\n');
+        out.push_str(&" ".repeat(width));
+    }
+    Ok(())
+}
+
+fn write_string(s: &str, out: &mut String) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\u{8}' => out.push_str("\\b"),
+            '\u{c}' => out.push_str("\\f"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(text: &str) -> String {
+        to_string(&parse(text).unwrap(), None).unwrap()
+    }
+
+    fn error_offset(text: &str) -> usize {
+        parse(text).unwrap_err().offset
+    }
+
+    #[test]
+    fn round_trips() {
+        assert_eq!(round_trip("null"), "null");
+        assert_eq!(round_trip(" [1, -2.5, true, false, null] "), "[1,-2.5,true,false,null]");
+        assert_eq!(round_trip("{\"b\": [1, {\"c\": {}}], \"a\": []}"), "{\"a\":[],\"b\":[1,{\"c\":{}}]}");
+        assert_eq!(round_trip("\"tab\\t quote\\\" \\u00e9 \\ud83d\\ude00\""), "\"tab\\t quote\\\" \u{e9} \u{1f600}\"");
+        assert_eq!(round_trip("1e3"), "1000.0");
+        assert_eq!(round_trip("12345678901234567890"), "12345678901234567000.0");
+    }
+
+    #[test]
+    fn values() {
+        assert_eq!(parse("42"), Ok(Object::Number(Number::Int(42))));
+        assert_eq!(parse("-0"), Ok(Object::Number(Number::Int(0))));
+        for &number in ["0.5", "-1.25e2", "1E-3", "2e+2"].iter() {
+            assert_eq!(parse(number).unwrap(), Object::Number(Number::Float(number.parse().unwrap(), number.to_string())));
+        }
+        assert_eq!(parse("[]"), Ok(Object::List(Box::new(vec![]))));
+        assert_eq!(to_string(&parse("[1,[2]]").unwrap(), Some(2)).unwrap(), "[\n  1,\n  [\n    2\n  ]\n]");
+    }
+
+    #[test]
+    fn error_offsets() {
+        assert_eq!(error_offset(""), 0);
+        assert_eq!(error_offset("[1, 2"), 5);
+        assert_eq!(error_offset("[1 2]"), 3);
+        assert_eq!(error_offset("{\"a\" 1}"), 5);
+        assert_eq!(error_offset("{1: 2}"), 1);
+        assert_eq!(error_offset("\"abc"), 4);
+        assert_eq!(error_offset("tru"), 0);
+        assert_eq!(error_offset("[1] x"), 4);
+        assert_eq!(error_offset("-"), 0);
+        for &number in ["01", "-01", "-.5", "1.", "1.e3", "1e", "1e+", "--1"].iter() {
+            assert_eq!(parse(number).unwrap_err().message, "Invalid number", "{}", number);
+        }
+        assert_eq!(error_offset(".5"), 0);
+        assert_eq!(error_offset("+1"), 0);
+        assert_eq!(error_offset("[1, 02]"), 4);
+        assert_eq!(parse("[1 2]").unwrap_err().to_string(), "Expected ',' or ']' at byte 3");
+    }
+
+    #[test]
+    fn nesting_limit() {
+        let nested = |depth: usize| format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+        assert!(parse(&nested(MAX_NESTING)).is_ok());
+        let error = parse(&nested(MAX_NESTING + 1)).unwrap_err();
+        assert_eq!(error.offset, MAX_NESTING);
+        assert_eq!(error.message, format!("Nested more than {} deep", MAX_NESTING));
+        // far deeper than the stack could take
+        assert_eq!(error_offset(&"[{\"a\":".repeat(2_000_000)), MAX_NESTING / 2 * 6);
+    }
+
+    #[test]
+    fn indent() {
+        let list = parse("[1, [2]]").unwrap();
+        assert_eq!(to_string(&list, Some(2)).unwrap(), "[\n  1,\n  [\n    2\n  ]\n]");
+        assert_eq!(to_string(&list, Some(MAX_INDENT)).unwrap().lines().last(), Some("]"));
+        assert!(to_string(&list, Some(MAX_INDENT + 1)).is_err());
+        assert!(to_string(&list, Some(::std::usize::MAX)).is_err());
+    }
+
+    #[test]
+    fn unwritable() {
+        assert!(to_string(&Object::Number(Number::Float(::std::f64::NAN, "nan".to_string())), None).is_err());
+        let mut table = HashMap::new();
+        table.insert(Object::Number(Number::Int(1)), Object::Nil);
+        assert!(to_string(&Object::HashTable(HashTable(table)), None).is_err());
+    }
+}
@@ -0,0 +1,190 @@
+//! Splitting source text into tokens that keep their positions.
+//!
+//! Lexing never fails: text that cannot be parsed still becomes tokens, so the
+//! repl can tell whether input is finished and tools can work on broken code.
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum TokenKind {
+    OpenParen,
+    CloseParen,
+    OpenBracket,
+    CloseBracket,
+    Quote,
+    String,
+    UnterminatedString,
+    Atom,
+    Comment,
+    Whitespace,
+}
+
+/// A token covering the bytes `start..end` of the source.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Token {
+    pub fn text<'a>(&self, source: &'a str) -> &'a str {
+        &source[self.start..self.end]
+    }
+}
+
+pub fn lex(source: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = source.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        let kind = match c {
+            '(' => TokenKind::OpenParen,
+            ')' => TokenKind::CloseParen,
+            '[' => TokenKind::OpenBracket,
+            ']' => TokenKind::CloseBracket,
+            '\'' => TokenKind::Quote,
+            '"' => {
+                let mut kind = TokenKind::UnterminatedString;
+                while let Some((_, c)) = chars.next() {
+                    match c {
+                        '\\' => {
+                            chars.next();
+                        }
+                        '"' => {
+                            kind = TokenKind::String;
+                            break;
+                        }
+                        _ => {}
+                    }
+                }
+                kind
+            }
+            ';' => {
+                while let Some(&(_, c)) = chars.peek() {
+                    if c == '\n' {
+                        break;
+                    }
+                    chars.next();
+                }
+                TokenKind::Comment
+            }
+            c if c.is_whitespace() => {
+                while let Some(&(_, c)) = chars.peek() {
+                    if !c.is_whitespace() {
+                        break;
+                    }
+                    chars.next();
+                }
+                TokenKind::Whitespace
+            }
+            _ => {
+                while let Some(&(_, c)) = chars.peek() {
+                    if ends_atom(c) {
+                        break;
+                    }
+                    chars.next();
+                }
+                TokenKind::Atom
+            }
+        };
+        let end = chars.peek().map(|&(i, _)| i).unwrap_or(source.len());
+        tokens.push(Token {
+            kind: kind,
+            start: start,
+            end: end,
+        });
+    }
+    tokens
+}
+
+fn ends_atom(c: char) -> bool {
+    c.is_whitespace() || c == '(' || c == ')' || c == '[' || c == ']' || c == '"' || c == ';'
+}
+
+/// Whether `source` has no unclosed brackets or strings, so that the repl can
+/// stop reading more lines. A trailing quote is incomplete; extra closing
+/// brackets count as complete, for the parser to report.
+pub fn is_complete(source: &str) -> bool {
+    let mut depth: usize = 0;
+    let mut quoting = false;
+    for token in lex(source) {
+        match token.kind {
+            TokenKind::Whitespace | TokenKind::Comment => continue,
+            _ => quoting = token.kind == TokenKind::Quote,
+        }
+        match token.kind {
+            TokenKind::OpenParen | TokenKind::OpenBracket => depth += 1,
+            TokenKind::CloseParen | TokenKind::CloseBracket => {
+                if depth == 0 {
+                    return true;
+                }
+                depth -= 1;
+            }
+            TokenKind::UnterminatedString => return false,
+            _ => {}
+        }
+    }
+    depth == 0 && !quoting
+}
+
+/// The 1-based line and column of the byte `offset` in `source`.
+pub fn line_column(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset];
+    let line = before.matches('\n').count() + 1;
+    let column = before.rfind('\n').map(|newline| before[newline + 1..].chars().count()).unwrap_or(before.chars().count()) + 1;
+    (line, column)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(source: &str) -> Vec<(TokenKind, &str)> {
+        lex(source).into_iter().map(|token| (token.kind, token.text(source))).collect()
+    }
+
+    #[test]
+    fn tokens() {
+        assert_eq!(kinds("(f [x] 'y)"),
+                   vec![(TokenKind::OpenParen, "("),
+                        (TokenKind::Atom, "f"),
+                        (TokenKind::Whitespace, " "),
+                        (TokenKind::OpenBracket, "["),
+                        (TokenKind::Atom, "x"),
+                        (TokenKind::CloseBracket, "]"),
+                        (TokenKind::Whitespace, " "),
+                        (TokenKind::Quote, "'"),
+                        (TokenKind::Atom, "y"),
+                        (TokenKind::CloseParen, ")")]);
+        assert_eq!(kinds("a;b c\nd"),
+                   vec![(TokenKind::Atom, "a"), (TokenKind::Comment, ";b c"), (TokenKind::Whitespace, "\n"), (TokenKind::Atom, "d")]);
+        assert_eq!(kinds("\"a \\\" ; b\"c"), vec![(TokenKind::String, "\"a \\\" ; b\""), (TokenKind::Atom, "c")]);
+        assert_eq!(kinds("x\"ab"), vec![(TokenKind::Atom, "x"), (TokenKind::UnterminatedString, "\"ab")]);
+        assert_eq!(kinds(""), vec![]);
+    }
+
+    #[test]
+    fn complete() {
+        assert!(is_complete(""));
+        assert!(is_complete("(+ 1 2)"));
+        assert!(is_complete("(f [x] y) ; (unclosed"));
+        assert!(is_complete("(print \"(\")"));
+        assert!(is_complete("'x"));
+        // extra closers are for the parser to report
+        assert!(is_complete("(f))"));
+        assert!(!is_complete("(define (f x)\n  (+ x"));
+        assert!(!is_complete("[(= n 0)"));
+        assert!(!is_complete("(print \"a\nb"));
+        assert!(!is_complete("(print \"\\\")"));
+        assert!(!is_complete("'"));
+        assert!(!is_complete("(f ' ; comment\n"));
+    }
+
+    #[test]
+    fn line_columns() {
+        let source = "(a\n  é b)";
+        assert_eq!(line_column(source, 0), (1, 1));
+        assert_eq!(line_column(source, 2), (1, 3));
+        assert_eq!(line_column(source, 3), (2, 1));
+        assert_eq!(line_column(source, source.find('b').unwrap()), (2, 5));
+        assert_eq!(line_column(source, source.len()), (2, 7));
+    }
+}
@@ -0,0 +1,33 @@
+//! A basic lisp interpreter.
+//!
+//! Host programs embed it through `Interpreter`; the `rlisp` binary is a repl
+//! and script runner built on the same type.
+
+#[macro_use]
+extern crate lazy_static;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[macro_use]
+pub mod convert;
+pub mod lexer;
+pub mod parser;
+pub mod syntax;
+pub mod format;
+pub mod lint;
+pub mod data;
+pub mod eval;
+pub mod debug;
+pub mod stdlisp;
+pub mod json;
+pub mod port;
+pub mod module;
+mod compiler;
+mod vm;
+mod interpreter;
+#[cfg(feature = "serde")]
+mod serde_impl;
+
+pub use data::{Object, Number, Boolean, HashTable, Condition, CallSite, Function, BuiltinFn, Arity, Env, Environment, Evaluator, Limits};
+pub use port::Port;
+pub use convert::{FromLisp, ToLisp};
+pub use interpreter::{Interpreter, Error};
@@ -0,0 +1,264 @@
+//! Static checks on source, without running it.
+//!
+//! The linter reports symbols that nothing binds, calls to builtins and
+//! top-level functions with the wrong number of args, params a function never
+//! uses, `cond`s without an `else` case and definitions or params that shadow
+//! a builtin. Names containing a `/` come from modules and are not checked, and
+//! params starting with `_` may go unused.
+
+use data::{Arity, LispFn};
+use eval::SPECIAL_FORMS;
+use std::collections::{HashMap, HashSet};
+use stdlisp::BASE_FUNCTIONS;
+use syntax::{Node, NodeKind};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    /// What is checked, such as "unbound-symbol"; see `lint`.
+    pub kind: &'static str,
+    pub message: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Checks forms read from `source`, returning warnings in source order.
+/// Warning kinds are unbound-symbol, arity, unused-parameter, cond-without-else
+/// and shadowed-builtin.
+pub fn lint(nodes: &[Node], source: &str) -> Vec<Warning> {
+    let mut linter = Linter {
+        source: source,
+        known: HashSet::new(),
+        arities: HashMap::new(),
+        builtins: HashSet::new(),
+        warnings: Vec::new(),
+    };
+    for &(name, ref function) in BASE_FUNCTIONS.iter() {
+        linter.known.insert(name.to_string());
+        linter.builtins.insert(name.to_string());
+        if let LispFn::Builtin(ref builtin) = *function.procedure {
+            linter.arities.insert(name.to_string(), builtin.arity());
+        }
+    }
+    linter.known.extend(SPECIAL_FORMS.iter().map(|form| form.to_string()));
+    linter.known.insert("else".to_string());
+    for node in nodes {
+        linter.collect_definitions(node, true);
+    }
+    for node in nodes {
+        linter.walk(node, &HashSet::new());
+    }
+    linter.warnings.sort_by_key(|warning| warning.start);
+    linter.warnings
+}
+
+struct Linter<'a> {
+    source: &'a str,
+    // names bound by builtins and any define
+    known: HashSet<String>,
+    // functions whose arity is known, by name
+    arities: HashMap<String, Arity>,
+    builtins: HashSet<String>,
+    warnings: Vec<Warning>,
+}
+
+impl<'a> Linter<'a> {
+    fn warn(&mut self, kind: &'static str, message: String, node: &Node) {
+        self.warnings.push(Warning {
+            kind: kind,
+            message: message,
+            start: node.start,
+            end: node.end,
+        });
+    }
+
+    // Adds the names bound by every `define` in `node`, since `define` binds
+    // at top level wherever it is evaluated. Only top-level function
+    // definitions have a known arity; others may not have run.
+    fn collect_definitions(&mut self, node: &Node, top_level: bool) {
+        if let NodeKind::Quote(..) = node.kind {
+            return;
+        }
+        if node.head(self.source) == Some("define") {
+            if let Some(target) = node.forms().get(1) {
+                match target.kind {
+                    NodeKind::List(_) => {
+                        let parts = target.forms();
+                        if let Some(name) = parts.first().and_then(|name| name.symbol(self.source)) {
+                            self.known.insert(name.to_string());
+                            if top_level {
+                                self.arities.insert(name.to_string(), Arity::Exactly(parts.len() - 1));
+                            } else {
+                                self.arities.remove(name);
+                            }
+                        }
+                    }
+                    _ => {
+                        if let Some(name) = target.symbol(self.source) {
+                            self.known.insert(name.to_string());
+                            self.arities.remove(name);
+                        }
+                    }
+                }
+            }
+        }
+        for child in node.children() {
+            self.collect_definitions(child, false);
+        }
+    }
+
+    // Checks `node`, where `locals` are the params of the functions around it.
+    fn walk(&mut self, node: &Node, locals: &HashSet<String>) {
+        match node.kind {
+            NodeKind::Atom => {
+                if let Some(name) = node.symbol(self.source) {
+                    if !self.known.contains(name) && !locals.contains(name) && !name.contains('/') {
+                        self.warn("unbound-symbol", format!("{} is not bound", name), node);
+                    }
+                }
+                return;
+            }
+            NodeKind::Quote(..) | NodeKind::String | NodeKind::Comment => return,
+            NodeKind::List(_) | NodeKind::Case(_) => {}
+        }
+        let forms = node.forms();
+        match node.head(self.source) {
+            Some("quote") | Some("module") => return,
+            Some("define") if forms.len() > 1 => {
+                match forms[1].kind {
+                    NodeKind::List(_) => {
+                        let declaration = forms[1].forms();
+                        if let Some(name) = declaration.first() {
+                            self.check_shadowing(name);
+                        }
+                        self.function(&declaration[declaration.len().min(1)..], &forms[2..], locals);
+                    }
+                    _ => {
+                        self.check_shadowing(forms[1]);
+                        for form in &forms[2..] {
+                            self.walk(form, locals);
+                        }
+                    }
+                }
+                return;
+            }
+            Some("lambda") if forms.len() > 1 => {
+                self.function(&forms[1].forms(), &forms[2..], locals);
+                return;
+            }
+            Some("guard") if forms.len() > 1 => {
+                let spec = forms[1].forms();
+                let mut clause_locals = locals.clone();
+                clause_locals.extend(spec.first().and_then(|var| var.symbol(self.source)).map(|var| var.to_string()));
+                for clause in spec.iter().skip(1) {
+                    self.walk(clause, &clause_locals);
+                }
+                for form in &forms[2..] {
+                    self.walk(form, locals);
+                }
+                return;
+            }
+            Some("cond") => {
+                let has_else = forms.last().map(|last| {
+                    last.kind != NodeKind::Atom && last.forms().first().and_then(|case| case.symbol(self.source)) == Some("else")
+                });
+                if has_else == Some(false) {
+                    self.warn("cond-without-else", "cond without an else case".to_string(), node);
+                }
+            }
+            Some(name) if !locals.contains(name) => {
+                if let NodeKind::List(_) = node.kind {
+                    let args = forms.len() - 1;
+                    match self.arities.get(name) {
+                        Some(arity) if !arity.accepts(args) => {
+                            let message = format!("{} takes {}; got {}", name, arity, args);
+                            self.warn("arity", message, node);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+        for form in forms {
+            self.walk(form, locals);
+        }
+    }
+
+    fn function(&mut self, params: &[&Node], body: &[&Node], locals: &HashSet<String>) {
+        let mut body_locals = locals.clone();
+        for param in params {
+            self.check_shadowing(param);
+            if let Some(name) = param.symbol(self.source) {
+                body_locals.insert(name.to_string());
+                if !name.starts_with('_') && !body.iter().any(|form| self.mentions(form, name)) {
+                    self.warn("unused-parameter", format!("{} is never used", name), param);
+                }
+            }
+        }
+        for form in body {
+            self.walk(form, &body_locals);
+        }
+    }
+
+    fn check_shadowing(&mut self, node: &Node) {
+        if let Some(name) = node.symbol(self.source) {
+            if self.builtins.contains(name) {
+                self.warn("shadowed-builtin", format!("{} shadows a builtin", name), node);
+            }
+        }
+    }
+
+    fn mentions(&self, node: &Node, name: &str) -> bool {
+        match node.kind {
+            NodeKind::Atom => node.symbol(self.source) == Some(name),
+            NodeKind::List(_) if node.head(self.source) == Some("quote") => false,
+            NodeKind::List(ref children) | NodeKind::Case(ref children) => children.iter().any(|child| self.mentions(child, name)),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use syntax::read;
+
+    // The kind and text of each warning for `source`.
+    fn warnings(source: &str) -> Vec<(&'static str, &str)> {
+        let (nodes, errors) = read(source);
+        assert!(errors.is_empty());
+        lint(&nodes, source).into_iter().map(|warning| (warning.kind, &source[warning.start..warning.end])).collect()
+    }
+
+    #[test]
+    fn unbound_symbol() {
+        assert_eq!(warnings("(define (f x) (g x))"), vec![("unbound-symbol", "g")]);
+        assert_eq!(warnings("(define (f x) (g x)) (define (g x) (f x))"), vec![]);
+        assert_eq!(warnings("(geo/area 2)"), vec![]);
+    }
+
+    #[test]
+    fn arity() {
+        assert_eq!(warnings("(define (f x y) (+ x y)) (f 1)"), vec![("arity", "(f 1)")]);
+        assert_eq!(warnings("(+ 1 2 3) (list)"), vec![]);
+    }
+
+    #[test]
+    fn unused_parameter() {
+        assert_eq!(warnings("(define (f x y) x)"), vec![("unused-parameter", "y")]);
+        assert_eq!(warnings("(define (f x _y) x)"), vec![]);
+    }
+
+    #[test]
+    fn cond_without_else() {
+        assert_eq!(warnings("(define (f n) (cond [(= n 0) 1] [(= n 1) 2]))").iter().map(|w| w.0).collect::<Vec<_>>(),
+                   vec!["cond-without-else"]);
+        assert_eq!(warnings("(define (f n) (cond [(= n 0) 1] [else 2]))"), vec![]);
+    }
+
+    #[test]
+    fn shadowed_builtin() {
+        assert_eq!(warnings("(define (f list) list)"), vec![("shadowed-builtin", "list")]);
+        assert_eq!(warnings("(define list 1)"), vec![("shadowed-builtin", "list")]);
+    }
+}
@@ -0,0 +1,606 @@
+//! A language server for rlisp files, run as `rlisp lsp` by editors, which
+//! talk to it over stdin and stdout.
+//!
+//! Documents are re-read on every change. The server reports syntax errors and
+//! the linter's warnings, completes builtins, special forms and the document's
+//! `define`s, and offers hover, go-to-definition and document symbols for them.
+//! Positions are counted in utf-16 code units, as the protocol asks.
+
+use rlisp::{Env, HashTable, Number, Object};
+use rlisp::data::{Boolean, LispFn};
+use rlisp::eval::{SPECIAL_FORMS, special_form_doc};
+use rlisp::json;
+use rlisp::lexer::{TokenKind, lex};
+use rlisp::lint::lint;
+use rlisp::syntax::{Node, NodeKind, SyntaxError, read};
+use std::collections::HashMap;
+use std::io::{self, BufRead, Read, Write};
+
+// protocol constants
+const FULL_SYNC: i64 = 1;
+const ERROR_SEVERITY: i64 = 1;
+const WARNING_SEVERITY: i64 = 2;
+const FUNCTION_KIND: i64 = 3;
+const VARIABLE_KIND: i64 = 6;
+const KEYWORD_KIND: i64 = 14;
+const FUNCTION_SYMBOL: i64 = 12;
+const VARIABLE_SYMBOL: i64 = 13;
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+// the longest message body read, far beyond any source file
+const MAX_CONTENT_LENGTH: usize = 64 << 20;
+
+struct Document {
+    text: String,
+    nodes: Vec<Node>,
+    errors: Vec<SyntaxError>,
+}
+
+impl Document {
+    fn new(text: String) -> Document {
+        let (nodes, errors) = read(&text);
+        Document {
+            text: text,
+            nodes: nodes,
+            errors: errors,
+        }
+    }
+}
+
+// A top-level `define`.
+struct Definition {
+    name: String,
+    // `None` for a variable
+    params: Option<Vec<String>>,
+    // from the comment lines just above it
+    doc: String,
+    form: (usize, usize),
+    name_span: (usize, usize),
+}
+
+struct Server {
+    builtins: Env,
+    documents: HashMap<String, Document>,
+    shutdown: bool,
+}
+
+/// Answers requests on stdin until the client sends `exit`.
+pub fn serve() {
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+    let mut server = Server {
+        builtins: Env::new(),
+        documents: HashMap::new(),
+        shutdown: false,
+    };
+    loop {
+        let message = match read_message(&mut input) {
+            Ok(Some(Ok(message))) => message,
+            Ok(Some(Err(error))) => {
+                // the message is skipped, and the client told why
+                if let Err(e) = write_message(&error_reply(Object::Nil, PARSE_ERROR, error)) {
+                    eprintln!("Failed to write a message: {}", e);
+                    return;
+                }
+                continue;
+            }
+            Ok(None) => break,
+            Err(e) => {
+                eprintln!("Failed to read a message: {}", e);
+                break;
+            }
+        };
+        if get(&message, &["method"]) == Some(&Object::String("exit".to_string())) {
+            break;
+        }
+        for reply in server.handle(&message) {
+            if let Err(e) = write_message(&reply) {
+                eprintln!("Failed to write a message: {}", e);
+                return;
+            }
+        }
+    }
+    if !server.shutdown {
+        eprintln!("Exiting without a shutdown request");
+    }
+}
+
+// Reads a message framed by a Content-Length header; `None` at the end of
+// input. A body that is too long or not JSON is skipped and described, so
+// that the next message can still be read.
+fn read_message<R: BufRead>(input: &mut R) -> io::Result<Option<Result<Object, String>>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if try!(input.read_line(&mut header)) == 0 {
+            return Ok(None);
+        }
+        let header = header.trim();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+    let length = match length {
+        Some(length) => length,
+        None => return Err(io::Error::new(io::ErrorKind::InvalidData, "message without a Content-Length")),
+    };
+    if length > MAX_CONTENT_LENGTH {
+        let skipped = try!(io::copy(&mut Read::take(&mut *input, length as u64), &mut io::sink()));
+        if skipped < length as u64 {
+            return Ok(None);
+        }
+        return Ok(Some(Err(format!("Message of {} bytes is longer than {}", length, MAX_CONTENT_LENGTH))));
+    }
+    let mut body = vec![0; length];
+    try!(input.read_exact(&mut body));
+    let body = match String::from_utf8(body) {
+        Ok(body) => body,
+        Err(e) => return Ok(Some(Err(format!("Message is not utf-8: {}", e)))),
+    };
+    Ok(Some(json::parse(&body).map_err(|e| format!("Message is not JSON: {}", e))))
+}
+
+fn write_message(message: &Object) -> io::Result<()> {
+    let body = try!(json::to_string(message, None).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)));
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    try!(write!(stdout, "Content-Length: {}\r\n\r\n{}", body.len(), body));
+    stdout.flush()
+}
+
+impl Server {
+    // The messages to send in answer to `message`.
+    fn handle(&mut self, message: &Object) -> Vec<Object> {
+        let method = match get(message, &["method"]) {
+            Some(&Object::String(ref method)) => method.clone(),
+            _ => return Vec::new(),
+        };
+        let params = get(message, &["params"]).cloned().unwrap_or(Object::Nil);
+        let id = match get(message, &["id"]) {
+            Some(id) => id.clone(),
+            None => return self.notify(&method, &params),
+        };
+        let result = match method.as_str() {
+            "initialize" => Ok(initialize_result()),
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Object::Nil)
+            }
+            "textDocument/completion" => self.with_document(&params, |server, document, _| server.completion(document)),
+            "textDocument/hover" => self.with_document(&params, |server, document, offset| server.hover(document, offset)),
+            "textDocument/definition" => {
+                let uri = get(&params, &["textDocument", "uri"]).cloned().unwrap_or(Object::Nil);
+                self.with_document(&params, |_, document, offset| definition(document, offset, uri))
+            }
+            "textDocument/documentSymbol" => self.with_document(&params, |_, document, _| document_symbols(document)),
+            _ => Err((METHOD_NOT_FOUND, format!("Unknown method {}", method))),
+        };
+        let reply = match result {
+            Ok(result) => object(vec![("jsonrpc", string("2.0")), ("id", id), ("result", result)]),
+            Err((code, message)) => error_reply(id, code, message),
+        };
+        vec![reply]
+    }
+
+    fn notify(&mut self, method: &str, params: &Object) -> Vec<Object> {
+        let uri = match get(params, &["textDocument", "uri"]) {
+            Some(&Object::String(ref uri)) => uri.clone(),
+            _ => return Vec::new(),
+        };
+        match method {
+            "textDocument/didOpen" => {
+                if let Some(&Object::String(ref text)) = get(params, &["textDocument", "text"]) {
+                    self.documents.insert(uri.clone(), Document::new(text.clone()));
+                }
+            }
+            "textDocument/didChange" => {
+                // the server asks for whole documents, so the last change is all of it
+                let text = match get(params, &["contentChanges"]) {
+                    Some(&Object::List(ref changes)) => changes.last().and_then(|change| get(change, &["text"])).cloned(),
+                    _ => None,
+                };
+                if let Some(Object::String(text)) = text {
+                    self.documents.insert(uri.clone(), Document::new(text));
+                }
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                return vec![diagnostics_message(&uri, Vec::new())];
+            }
+            _ => return Vec::new(),
+        }
+        match self.documents.get(&uri) {
+            Some(document) => vec![diagnostics_message(&uri, self.diagnostics(document))],
+            None => Vec::new(),
+        }
+    }
+
+    // Runs `f` with the document named in `params` and the offset of the
+    // position given, if any.
+    fn with_document<F>(&self, params: &Object, f: F) -> Result<Object, (i64, String)>
+        where F: FnOnce(&Server, &Document, usize) -> Object
+    {
+        let uri = match get(params, &["textDocument", "uri"]) {
+            Some(&Object::String(ref uri)) => uri,
+            _ => return Err((INVALID_PARAMS, "Missing textDocument.uri".to_string())),
+        };
+        let document = match self.documents.get(uri) {
+            Some(document) => document,
+            None => return Err((INVALID_PARAMS, format!("{} is not open", uri))),
+        };
+        let offset = match (get(params, &["position", "line"]), get(params, &["position", "character"])) {
+            (Some(&Object::Number(Number::Int(line))), Some(&Object::Number(Number::Int(character)))) => {
+                offset_at(&document.text, line as usize, character as usize)
+            }
+            _ => 0,
+        };
+        Ok(f(self, document, offset))
+    }
+
+    fn diagnostics(&self, document: &Document) -> Vec<Object> {
+        let text = &document.text;
+        let mut diagnostics = document.errors
+            .iter()
+            .map(|error| diagnostic(text, error.start, error.end, ERROR_SEVERITY, &error.message, None))
+            .collect::<Vec<_>>();
+        for warning in lint(&document.nodes, text) {
+            diagnostics.push(diagnostic(text, warning.start, warning.end, WARNING_SEVERITY, &warning.message, Some(warning.kind)));
+        }
+        diagnostics
+    }
+
+    fn completion(&self, document: &Document) -> Object {
+        let mut items = Vec::new();
+        for form in SPECIAL_FORMS.iter() {
+            items.push(object(vec![("label", string(form)), ("kind", int(KEYWORD_KIND)), ("detail", string("special form"))]));
+        }
+        let mut builtins = self.builtins.variables.iter().collect::<Vec<_>>();
+        builtins.sort_by(|a, b| a.0.cmp(b.0));
+        for (name, value) in builtins {
+            let detail = match *value {
+                Object::Function(ref function) => {
+                    match *function.procedure {
+                        LispFn::Builtin(ref builtin) => format!("builtin taking {}", builtin.arity()),
+                        _ => "function".to_string(),
+                    }
+                }
+                ref value => value.type_name().to_string(),
+            };
+            items.push(object(vec![("label", Object::String(name.clone())),
+                                   ("kind", int(FUNCTION_KIND)),
+                                   ("detail", Object::String(detail))]));
+        }
+        for definition in definitions(document) {
+            let kind = if definition.params.is_some() { FUNCTION_KIND } else { VARIABLE_KIND };
+            items.push(object(vec![("label", Object::String(definition.name.clone())),
+                                   ("kind", int(kind)),
+                                   ("detail", Object::String(signature(&definition)))]));
+        }
+        Object::List(Box::new(items))
+    }
+
+    fn hover(&self, document: &Document, offset: usize) -> Object {
+        let (name, start, end) = match symbol_at(&document.text, offset) {
+            Some(symbol) => symbol,
+            None => return Object::Nil,
+        };
+        let contents = if let Some(doc) = special_form_doc(&name) {
+            format!("**{}** special form\n\n{}", name, doc)
+        } else if let Some(definition) = definitions(document).into_iter().find(|definition| definition.name == name) {
+            format!("`{}`\n\n{}", signature(&definition), definition.doc)
+        } else {
+            match self.builtins.variables.get(&name) {
+                Some(&Object::Function(ref function)) => {
+                    match *function.procedure {
+                        LispFn::Builtin(ref builtin) => {
+                            format!("**{}** builtin taking {}\n\n{}", name, builtin.arity(), builtin.doc().unwrap_or(""))
+                        }
+                        _ => return Object::Nil,
+                    }
+                }
+                _ => return Object::Nil,
+            }
+        };
+        let contents = object(vec![("kind", string("markdown")), ("value", Object::String(contents.trim().to_string()))]);
+        object(vec![("contents", contents), ("range", range(&document.text, start, end))])
+    }
+}
+
+fn error_reply(id: Object, code: i64, message: String) -> Object {
+    let error = object(vec![("code", int(code)), ("message", Object::String(message))]);
+    object(vec![("jsonrpc", string("2.0")), ("id", id), ("error", error)])
+}
+
+fn initialize_result() -> Object {
+    let capabilities = object(vec![("textDocumentSync", int(FULL_SYNC)),
+                                   ("completionProvider", object(Vec::new())),
+                                   ("hoverProvider", Object::Boolean(Boolean::True)),
+                                   ("definitionProvider", Object::Boolean(Boolean::True)),
+                                   ("documentSymbolProvider", Object::Boolean(Boolean::True))]);
+    let info = object(vec![("name", string(super::NAME)), ("version", string(super::VERSION))]);
+    object(vec![("capabilities", capabilities), ("serverInfo", info)])
+}
+
+fn definition(document: &Document, offset: usize, uri: Object) -> Object {
+    let name = match symbol_at(&document.text, offset) {
+        Some((name, _, _)) => name,
+        None => return Object::Nil,
+    };
+    match definitions(document).into_iter().find(|definition| definition.name == name) {
+        Some(definition) => {
+            object(vec![("uri", uri), ("range", range(&document.text, definition.name_span.0, definition.name_span.1))])
+        }
+        None => Object::Nil,
+    }
+}
+
+fn document_symbols(document: &Document) -> Object {
+    let text = &document.text;
+    let symbols = definitions(document)
+        .into_iter()
+        .map(|definition| {
+            let kind = if definition.params.is_some() { FUNCTION_SYMBOL } else { VARIABLE_SYMBOL };
+            object(vec![("name", Object::String(definition.name.clone())),
+                        ("detail", Object::String(signature(&definition))),
+                        ("kind", int(kind)),
+                        ("range", range(text, definition.form.0, definition.form.1)),
+                        ("selectionRange", range(text, definition.name_span.0, definition.name_span.1))])
+        })
+        .collect();
+    Object::List(Box::new(symbols))
+}
+
+fn definitions(document: &Document) -> Vec<Definition> {
+    let text = &document.text;
+    let mut definitions = Vec::new();
+    let mut comments: Vec<&Node> = Vec::new();
+    for node in document.nodes.iter() {
+        if node.kind == NodeKind::Comment {
+            // only a run of comments on consecutive lines, ending just above the form, documents it
+            let separated = comments.last().map(|last| text[last.end..node.start].matches('\n').count() > 1).unwrap_or(false);
+            if separated {
+                comments.clear();
+            }
+            comments.push(node);
+            continue;
+        }
+        let attached = comments.last().map(|last| text[last.end..node.start].matches('\n').count() <= 1).unwrap_or(false);
+        let doc = if attached {
+            comments.iter().map(|comment| comment.text(text).trim_start_matches(';').trim()).collect::<Vec<_>>().join("\n")
+        } else {
+            String::new()
+        };
+        comments.clear();
+        if node.head(text) != Some("define") {
+            continue;
+        }
+        let forms = node.forms();
+        let target = match forms.get(1) {
+            Some(target) => target,
+            None => continue,
+        };
+        let (name_node, params) = match target.kind {
+            NodeKind::Atom => (*target, None),
+            NodeKind::List(_) => {
+                let parts = target.forms();
+                match parts.first() {
+                    Some(name) => (*name, Some(parts[1..].iter().map(|param| param.text(text).to_string()).collect())),
+                    None => continue,
+                }
+            }
+            _ => continue,
+        };
+        let name = match name_node.symbol(text) {
+            Some(name) => name.to_string(),
+            None => continue,
+        };
+        definitions.push(Definition {
+            name: name,
+            params: params,
+            doc: doc,
+            form: (node.start, node.end),
+            name_span: (name_node.start, name_node.end),
+        });
+    }
+    definitions
+}
+
+fn signature(definition: &Definition) -> String {
+    match definition.params {
+        Some(ref params) if params.is_empty() => format!("({})", definition.name),
+        Some(ref params) => format!("({} {})", definition.name, params.join(" ")),
+        None => definition.name.clone(),
+    }
+}
+
+// The symbol at or just before `offset`, with its span.
+fn symbol_at(text: &str, offset: usize) -> Option<(String, usize, usize)> {
+    lex(text)
+        .into_iter()
+        .find(|token| token.kind == TokenKind::Atom && token.start <= offset && offset <= token.end)
+        .map(|token| (token.text(text).to_string(), token.start, token.end))
+}
+
+fn diagnostics_message(uri: &str, diagnostics: Vec<Object>) -> Object {
+    let params = object(vec![("uri", string(uri)), ("diagnostics", Object::List(Box::new(diagnostics)))]);
+    object(vec![("jsonrpc", string("2.0")), ("method", string("textDocument/publishDiagnostics")), ("params", params)])
+}
+
+fn diagnostic(text: &str, start: usize, end: usize, severity: i64, message: &str, code: Option<&str>) -> Object {
+    let mut fields = vec![("range", range(text, start, end)),
+                          ("severity", int(severity)),
+                          ("source", string("rlisp")),
+                          ("message", string(message))];
+    if let Some(code) = code {
+        fields.push(("code", string(code)));
+    }
+    object(fields)
+}
+
+fn range(text: &str, start: usize, end: usize) -> Object {
+    object(vec![("start", position(text, start)), ("end", position(text, end))])
+}
+
+fn position(text: &str, offset: usize) -> Object {
+    let before = &text[..offset];
+    let line = before.matches('\n').count();
+    let line_start = before.rfind('\n').map(|newline| newline + 1).unwrap_or(0);
+    let character = before[line_start..].encode_utf16().count();
+    object(vec![("line", int(line as i64)), ("character", int(character as i64))])
+}
+
+// The byte offset of a protocol position, clamped to the text.
+fn offset_at(text: &str, line: usize, character: usize) -> usize {
+    let mut line_start = 0;
+    for _ in 0..line {
+        match text[line_start..].find('\n') {
+            Some(newline) => line_start += newline + 1,
+            None => return text.len(),
+        }
+    }
+    let mut units = 0;
+    for (i, c) in text[line_start..].char_indices() {
+        if units >= character || c == '\n' {
+            return line_start + i;
+        }
+        units += c.len_utf16();
+    }
+    text.len()
+}
+
+fn get<'a>(object: &'a Object, path: &[&str]) -> Option<&'a Object> {
+    let mut current = object;
+    for key in path {
+        current = match *current {
+            Object::HashTable(ref table) => {
+                match table.0.get(&Object::String(key.to_string())) {
+                    Some(value) => value,
+                    None => return None,
+                }
+            }
+            _ => return None,
+        };
+    }
+    Some(current)
+}
+
+fn object(fields: Vec<(&str, Object)>) -> Object {
+    Object::HashTable(HashTable(fields.into_iter().map(|(name, value)| (Object::String(name.to_string()), value)).collect()))
+}
+
+fn string(s: &str) -> Object {
+    Object::String(s.to_string())
+}
+
+fn int(i: i64) -> Object {
+    Object::Number(Number::Int(i))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const SOURCE: &'static str = "; adds one
+; to x
+(define (inc x) (+ x 1))
+
+; not about y
+
+(define y (inc 2))
+";
+
+    fn server_with(uri: &str, text: &str) -> Server {
+        let mut server = Server {
+            builtins: Env::new(),
+            documents: HashMap::new(),
+            shutdown: false,
+        };
+        let open = object(vec![("method", string("textDocument/didOpen")),
+                               ("params", object(vec![("textDocument", object(vec![("uri", string(uri)), ("text", string(text))]))]))]);
+        server.handle(&open);
+        server
+    }
+
+    fn request(server: &mut Server, method: &str, uri: &str, line: i64, character: i64) -> Object {
+        let position = object(vec![("line", int(line)), ("character", int(character))]);
+        let params = object(vec![("textDocument", object(vec![("uri", string(uri))])), ("position", position)]);
+        let reply = server.handle(&object(vec![("id", int(1)), ("method", string(method)), ("params", params)]));
+        get(&reply[0], &["result"]).cloned().unwrap()
+    }
+
+    #[test]
+    fn utf16_positions() {
+        // the emoji is four bytes and two utf-16 units
+        let text = "a\u{1F600}b\nc";
+        assert_eq!(offset_at(text, 0, 3), 5);
+        assert_eq!(offset_at(text, 1, 0), 7);
+        assert_eq!(offset_at(text, 0, 100), 6);
+        assert_eq!(offset_at(text, 5, 0), text.len());
+        assert_eq!(position(text, 5), object(vec![("line", int(0)), ("character", int(3))]));
+        assert_eq!(position(text, 8), object(vec![("line", int(1)), ("character", int(1))]));
+        for &(line, character) in [(0, 0), (0, 1), (0, 3), (0, 4), (1, 0), (1, 1)].iter() {
+            let offset = offset_at(text, line, character);
+            assert_eq!(position(text, offset), object(vec![("line", int(line as i64)), ("character", int(character as i64))]));
+        }
+    }
+
+    #[test]
+    fn definitions_and_docs() {
+        let definitions = definitions(&Document::new(SOURCE.to_string()));
+        assert_eq!(definitions.len(), 2);
+        assert_eq!((definitions[0].name.as_str(), definitions[0].doc.as_str()), ("inc", "adds one\nto x"));
+        assert_eq!(definitions[0].params, Some(vec!["x".to_string()]));
+        assert_eq!(signature(&definitions[0]), "(inc x)");
+        // a blank line parts a comment from the form below it
+        assert_eq!((definitions[1].name.as_str(), definitions[1].doc.as_str()), ("y", ""));
+        assert_eq!(definitions[1].params, None);
+    }
+
+    #[test]
+    fn requests() {
+        let uri = "file:///inc.lisp";
+        let mut server = server_with(uri, SOURCE);
+
+        let labels = match request(&mut server, "textDocument/completion", uri, 0, 0) {
+            Object::List(items) => items.iter().filter_map(|item| get(item, &["label"]).cloned()).collect::<Vec<_>>(),
+            other => panic!("expected completion items, got {:?}", other),
+        };
+        for label in ["inc", "y", "define", "+"].iter() {
+            assert!(labels.contains(&string(label)), "no completion for {}", label);
+        }
+
+        // on the inc in (inc 2)
+        let hover = request(&mut server, "textDocument/hover", uri, 6, 12);
+        assert_eq!(get(&hover, &["contents", "value"]), Some(&string("`(inc x)`\n\nadds one\nto x")));
+        let hover = request(&mut server, "textDocument/hover", uri, 2, 17);
+        assert!(get(&hover, &["contents", "value"]).is_some(), "no hover for +");
+        assert_eq!(request(&mut server, "textDocument/hover", uri, 3, 0), Object::Nil);
+
+        let definition = request(&mut server, "textDocument/definition", uri, 6, 12);
+        assert_eq!(get(&definition, &["uri"]), Some(&string(uri)));
+        assert_eq!(get(&definition, &["range", "start"]), Some(&object(vec![("line", int(2)), ("character", int(9))])));
+        assert_eq!(request(&mut server, "textDocument/definition", uri, 2, 17), Object::Nil);
+    }
+
+    #[test]
+    fn unreadable_messages() {
+        let framed = |body: &str| format!("Content-Length: {}\r\n\r\n{}", body.len(), body);
+        let input = format!("{}Content-Length: {}\r\n\r\n{}{}",
+                            framed("{not json"),
+                            MAX_CONTENT_LENGTH + 1,
+                            " ".repeat(MAX_CONTENT_LENGTH + 1),
+                            framed("{\"id\": 1}"));
+        let mut input = Cursor::new(input.into_bytes());
+        assert!(read_message(&mut input).unwrap().unwrap().unwrap_err().starts_with("Message is not JSON"));
+        assert!(read_message(&mut input).unwrap().unwrap().unwrap_err().contains("is longer than"));
+        // the message after them is still read
+        assert_eq!(read_message(&mut input).unwrap().unwrap().unwrap(), object(vec![("id", int(1))]));
+        assert!(read_message(&mut input).unwrap().is_none());
+    }
+}
@@ -1,39 +1,111 @@
 extern crate clap;
-#[macro_use]
-extern crate lazy_static;
-mod parser;
-mod data;
-mod eval;
-mod stdlisp;
+extern crate rlisp;
+extern crate rustyline;
 mod repl;
+mod backtrace;
+mod commands;
+mod debugger;
+mod helper;
+mod lsp;
+mod server;
+mod style;
+mod tools;
 
-use clap::App;
-use data::*;
-use repl::repl;
-use eval::run_file;
+use clap::{App, SubCommand};
+use rlisp::{Interpreter, Env, Evaluator};
+use rlisp::format::DEFAULT_WIDTH;
+use repl::{repl, run_file};
+use std::process;
+use std::thread;
+use tools::FormatMode;
 
 const NAME: &'static str = "rlisp";
 const VERSION: &'static str = "1.0";
 const AUTHOR: &'static str = "Edward Yang <edward.yang6771@gmail.com>";
 const INFO: &'static str = "A basic lisp interpreter in rust.";
+// enough for the tree-walker to reach MAX_DEPTH
+const STACK_SIZE: usize = 512 * 1024 * 1024;
+const MAX_DEPTH: usize = 10000;
 
 fn main() {
+    let child = thread::Builder::new().stack_size(STACK_SIZE).spawn(run).expect("Failed to start the interpreter thread.");
+    if child.join().is_err() {
+        process::exit(1);
+    }
+}
+
+fn run() {
     let matches = App::new(NAME)
         .version(VERSION)
         .author(AUTHOR)
         .about(INFO)
         .args_from_usage(
             "-i --interactive 'optional - Enables interactive repl - enabled if no file specified'
-            -f --file=[FILE] 'optional - specifies a file to load'")
+            -f --file=[FILE] 'optional - specifies a file to load'
+            --tree-walk 'optional - Evaluates with the reference tree-walker instead of the bytecode vm'
+            --no-color 'optional - Disables coloured output, as does setting NO_COLOR'
+            --debug 'optional - Pauses at (break) in a debugger that can step through calls'
+            --server=[ADDR] 'optional - Serves a repl over TCP at ADDR, or a unix socket at unix:PATH'")
+        .subcommand(SubCommand::with_name("lsp").about("Runs a language server over stdin and stdout"))
+        .subcommand(SubCommand::with_name("fmt")
+            .about("Formats lisp files, or stdin if none are given")
+            .args_from_usage(
+                "--check 'optional - Lists files that are not formatted instead of formatting them'
+                -w --write 'optional - Formats files in place'
+                --width=[WIDTH] 'optional - The line width to fit forms in, 80 by default'
+                [FILES]... 'The files to format'"))
+        .subcommand(SubCommand::with_name("lint")
+            .about("Reports likely mistakes in lisp files, or stdin if none are given")
+            .args_from_usage("[FILES]... 'The files to check'"))
         .get_matches();
-    let mut stdenv = Env::new();
-    if let Some(input) = matches.value_of("FILE") {
-        if matches.is_present("interactive") {
-            repl(Some(input));
+    if matches.subcommand_name() == Some("lsp") {
+        lsp::serve();
+        return;
+    }
+    if let Some(matches) = matches.subcommand_matches("lint") {
+        let files = matches.values_of("FILES").map(|files| files.into_iter().collect::<Vec<_>>()).unwrap_or_default();
+        process::exit(tools::lint_files(&files));
+    }
+    if let Some(matches) = matches.subcommand_matches("fmt") {
+        let files = matches.values_of("FILES").map(|files| files.into_iter().collect::<Vec<_>>()).unwrap_or_default();
+        let mode = if matches.is_present("check") {
+            FormatMode::Check
+        } else if matches.is_present("write") {
+            FormatMode::Write
         } else {
-            run_file(input, &mut stdenv);
+            FormatMode::Print
+        };
+        let width = match matches.value_of("WIDTH").map(|width| width.parse::<usize>()) {
+            Some(Ok(width)) => width,
+            Some(Err(_)) => {
+                eprintln!("--width takes a number of columns");
+                process::exit(2);
+            }
+            None => DEFAULT_WIDTH,
+        };
+        process::exit(tools::fmt(&files, mode, width));
+    }
+    style::init(matches.is_present("no-color"));
+    let mut stdenv = Env::new();
+    stdenv.limits.max_depth = Some(MAX_DEPTH);
+    if matches.is_present("tree-walk") {
+        stdenv.evaluator = Evaluator::TreeWalk;
+    }
+    if let Some(address) = matches.value_of("ADDR") {
+        if let Some(input) = matches.value_of("FILE") {
+            let mut interpreter = Interpreter::with_env(stdenv);
+            run_file(input, &mut interpreter);
+            stdenv = interpreter.env().clone();
         }
+        server::serve(address, stdenv);
     } else {
-        repl(None);
+        let mut interpreter = Interpreter::with_env(stdenv);
+        if matches.is_present("debug") {
+            debugger::attach(&mut interpreter);
+        }
+        match matches.value_of("FILE") {
+            Some(input) if !matches.is_present("interactive") => run_file(input, &mut interpreter),
+            file => repl(file, interpreter),
+        }
     }
 }
@@ -0,0 +1,230 @@
+//! Modules loaded with `require`.
+//!
+//! A module is a file whose first form may be `(module name (export names...))`;
+//! without one the module is named after the file and exports everything. Its
+//! exports are bound in the requiring env as `name/x`; the rest of its
+//! definitions are kept in its namespace, out of sight of the top level.
+//! Functions made in a module look names up in its namespace first, so they
+//! keep working from wherever they are called. Names that already contain a
+//! `/`, such as those bound by the modules it requires, are bound unchanged
+//! if the module lists them in its exports and kept in its namespace if not.
+
+use data::*;
+use eval::eval_form;
+use parser::read_file;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::mem;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Where the functions made in a module look names up before the top level:
+/// the module's private definitions, and the qualified names its exports are
+/// bound to.
+#[derive(Clone)]
+pub struct Namespace {
+    name: String,
+    // everything is exported without a module form
+    exports: Option<Arc<HashSet<String>>>,
+    private: Environment,
+}
+
+impl Namespace {
+    fn new(name: &str, exports: Option<HashSet<String>>) -> Namespace {
+        Namespace {
+            name: name.to_string(),
+            exports: exports.map(Arc::new),
+            private: Environment::new(HashMap::new()),
+        }
+    }
+    /// The value of `name` if the module defines it and does not export it.
+    pub fn private(&self, name: &str) -> Option<Object> {
+        self.private.get(name)
+    }
+    pub fn set_private(&self, name: &str, value: Object) {
+        self.private.define(name, value);
+    }
+    /// The name that `name` is bound to at top level if the module exports it.
+    pub fn qualify(&self, name: &str) -> Option<String> {
+        let exported = match self.exports {
+            Some(ref exports) => exports.contains(name),
+            None => !name.contains('/'),
+        };
+        match exported {
+            true if name.contains('/') => Some(name.to_string()),
+            true => Some(format!("{}/{}", self.name, name)),
+            false => None,
+        }
+    }
+}
+
+impl PartialEq for Namespace {
+    fn eq(&self, other: &Namespace) -> bool {
+        self.private == other.private
+    }
+}
+
+impl Eq for Namespace {}
+
+impl Hash for Namespace {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.private.hash(state);
+    }
+}
+
+/// Modules already loaded, by canonical path, and those being loaded.
+#[derive(Clone, Default)]
+pub struct Modules {
+    loaded: HashMap<PathBuf, HashMap<String, Object>>,
+    loading: Vec<PathBuf>,
+}
+
+/// The directories listed in `RLISP_PATH`.
+pub fn default_library_path() -> Vec<PathBuf> {
+    match env::var_os("RLISP_PATH") {
+        Some(paths) => env::split_paths(&paths).collect(),
+        None => Vec::new(),
+    }
+}
+
+/// Loads the module `name` if it has not been already and binds its
+/// definitions in `env`.
+pub fn require(name: &str, env: &mut Env) -> Result<(), Condition> {
+    let path = try!(resolve(name, env));
+    if let Some(position) = env.modules.loading.iter().position(|loading| *loading == path) {
+        let mut cycle = env.modules.loading[position..].iter().map(|p| p.display().to_string()).collect::<Vec<_>>();
+        cycle.push(path.display().to_string());
+        return Err(Condition::with_irritants("module-error",
+                                             format!("Cyclic require: {}", cycle.join(" -> ")),
+                                             vec![Object::String(name.to_string())]));
+    }
+    let bindings = match env.modules.loaded.get(&path) {
+        Some(bindings) => bindings.clone(),
+        None => {
+            env.modules.loading.push(path.clone());
+            let result = load(&path, env);
+            env.modules.loading.pop();
+            let bindings = try!(result);
+            env.modules.loaded.insert(path, bindings.clone());
+            bindings
+        }
+    };
+    for (name, value) in bindings {
+        env.variables.insert(name, value);
+    }
+    Ok(())
+}
+
+// Looks for the module next to the requiring module (or in the current
+// directory at top level), then in each directory of the library path.
+fn resolve(name: &str, env: &Env) -> Result<PathBuf, Condition> {
+    let mut file = PathBuf::from(name);
+    if file.extension().is_none() {
+        file.set_extension("lisp");
+    }
+    let mut dirs = vec![match env.modules.loading.last() {
+        Some(current) => current.parent().map(Path::to_path_buf).unwrap_or_default(),
+        None => PathBuf::from("."),
+    }];
+    if file.is_relative() {
+        dirs.extend(env.library_path.iter().cloned());
+    }
+    for dir in dirs {
+        let candidate = dir.join(&file);
+        if candidate.is_file() {
+            return candidate.canonicalize().map_err(|e| {
+                Condition::with_irritants("io-error", e.to_string(), vec![Object::String(name.to_string())])
+            });
+        }
+    }
+    Err(Condition::with_irritants("module-error",
+                                  format!("No module {:?} in the current directory or library path", name),
+                                  vec![Object::String(name.to_string())]))
+}
+
+fn load(path: &Path, env: &mut Env) -> Result<HashMap<String, Object>, Condition> {
+    let path_object = Object::String(path.display().to_string());
+    let exprs = try!(read_file(path));
+    let default_name = path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+    let (module_name, exports, body) = match exprs.split_first() {
+        Some((first, rest)) if is_module_form(first) => {
+            let (module_name, exports) = try!(parse_module_form(first));
+            (module_name, Some(exports), rest)
+        }
+        _ => (default_name, None, &exprs[..]),
+    };
+
+    // functions made here look names up in the module's namespace wherever
+    // they are called from; it is filled in once the module has run
+    let namespace = Namespace::new(&module_name, exports.clone());
+    let base = Env::new().variables;
+    let caller_variables = mem::replace(&mut env.variables, base.clone());
+    let caller_namespace = mem::replace(&mut env.namespace, Some(namespace.clone()));
+    let mut result = Ok(None);
+    for expr in body {
+        result = eval_form(expr, env);
+        if result.is_err() {
+            break;
+        }
+    }
+    env.namespace = caller_namespace;
+    let variables = mem::replace(&mut env.variables, caller_variables);
+    try!(result);
+
+    let definitions = variables.into_iter().filter(|&(ref name, ref value)| base.get(name) != Some(value)).collect::<HashMap<_, _>>();
+    if let Some(ref exports) = exports {
+        for export in exports {
+            if !definitions.contains_key(export) {
+                return Err(Condition::with_irritants("module-error",
+                                                     format!("Module {} exports {} but does not define it", module_name, export),
+                                                     vec![path_object]));
+            }
+        }
+    }
+    let mut bindings = HashMap::new();
+    for (name, value) in definitions {
+        match namespace.qualify(&name) {
+            Some(qualified) => {
+                bindings.insert(qualified, value);
+            }
+            None => namespace.set_private(&name, value),
+        }
+    }
+    Ok(bindings)
+}
+
+fn is_module_form(expr: &Expr) -> bool {
+    match *expr {
+        Expr::Exprs(ref exprs, _) => exprs.first() == Some(&Expr::Expr(Object::Symbol("module".to_string()))),
+        _ => false,
+    }
+}
+
+// (module name (export names...))
+fn parse_module_form(expr: &Expr) -> Result<(String, HashSet<String>), Condition> {
+    let invalid = || Condition::new("syntax-error", "Module forms look like (module name (export names...))".to_string());
+    let exprs = match *expr {
+        Expr::Exprs(ref exprs, _) if exprs.len() == 3 => exprs,
+        _ => return Err(invalid()),
+    };
+    let name = match exprs[1] {
+        Expr::Expr(Object::Symbol(ref name)) if !name.contains('/') => name.clone(),
+        _ => return Err(invalid()),
+    };
+    let mut exports = HashSet::new();
+    match exprs[2] {
+        Expr::Exprs(ref export, _) if export.first() == Some(&Expr::Expr(Object::Symbol("export".to_string()))) => {
+            for item in export.iter().skip(1) {
+                match *item {
+                    Expr::Expr(Object::Symbol(ref item)) => {
+                        exports.insert(item.clone());
+                    }
+                    _ => return Err(invalid()),
+                }
+            }
+        }
+        _ => return Err(invalid()),
+    }
+    Ok((name, exports))
+}
@@ -1,137 +1,188 @@
 use data::*;
+use lexer::{Token, TokenKind, lex, line_column};
 use std::fs::File;
 use std::io::prelude::*;
+use std::path::Path;
+use std::sync::Arc;
+
+/// How deeply lists, cases and quotes may be nested in source, so that reading
+/// and evaluating it cannot run out of stack.
+pub const MAX_NESTING: usize = 512;
 
 pub fn parse_file(filename: &str) -> Result<Vec<Expr>, String> {
-    let mut f: File;
-    match File::open(filename) {
-        Ok(r) => f = r,
-        Err(_) => return Err(format!("Failed to open file {:?}", filename))
-    }
-    let mut s = String::new();
-    match f.read_to_string(&mut s) {
-        Ok(_) => {},
-        Err(_) => return Err(format!("Failed to read file {:?}", filename))
-    }
-    return parse(&s)
+    read_file(Path::new(filename)).map_err(|condition| condition.message)
+}
+
+/// Reads and parses the file at `path`, with spans naming it. Files are loaded
+/// through this wherever they come from, whether run, `load`ed or
+/// `require`d.
+pub fn read_file(path: &Path) -> Result<Vec<Expr>, Condition> {
+    let path_object = Object::String(path.display().to_string());
+    let mut text = String::new();
+    try!(File::open(path).and_then(|mut file| file.read_to_string(&mut text)).map_err(|e| {
+        Condition::with_irritants("io-error", format!("Could not read {}: {}", path.display(), e), vec![path_object.clone()])
+    }));
+    parse_named(&path.display().to_string(), text)
+        .map_err(|message| Condition::with_irritants("syntax-error", message, vec![path_object]))
 }
 
 pub fn parse(data: &String) -> Result<Vec<Expr>, String> {
-    let parens = count_parens(data);
-    if parens.0 != parens.1 {
-        return Err("One or more unmatched parentheses.".to_string())
-    }
-    let mut tokens = tokenize(&lines_to_spaces(&data))
-        .iter()
-        .rev()
-        .map(|t| t.clone())
-        .collect::<Vec<String>>();
+    parse_named("<input>", data.clone())
+}
+
+/// Parses `text`, giving the forms spans in a source called `name`. Forms
+/// nested more than `MAX_NESTING` deep are a syntax error.
+pub fn parse_named(name: &str, text: String) -> Result<Vec<Expr>, String> {
+    let source = Arc::new(Source {
+        name: name.to_string(),
+        text: text,
+    });
+    let tokens = lex(&source.text)
+        .into_iter()
+        .filter(|token| token.kind != TokenKind::Whitespace && token.kind != TokenKind::Comment)
+        .collect::<Vec<_>>();
+    let mut parser = Parser {
+        source: &source.text,
+        shared: source.clone(),
+        tokens: tokens,
+        pos: 0,
+        depth: 0,
+    };
     let mut exprs = Vec::new();
-    while tokens.len() > 0 {
-        exprs.push(try!(tokens_to_expr(&mut tokens)));
-        for i in (0..tokens.len()).rev() {
-            if tokens[i] == " " {
-                tokens.remove(i);
-            }
-        }
+    while parser.pos < parser.tokens.len() {
+        exprs.push(try!(parser.expr()));
     }
     Ok(exprs)
 }
 
-fn count_parens(data: &String) -> (usize, usize) {
-    data.chars()
-        .fold((0, 0),
-        |acc, item| {
-            match item {
-                '(' => (acc.0 + 1, acc.1),
-                ')' => (acc.0, acc.1 + 1),
-                _   => acc
-            }
-        })
+struct Parser<'a> {
+    source: &'a str,
+    // the same source, for spans to share
+    shared: Arc<Source>,
+    tokens: Vec<Token>,
+    pos: usize,
+    // how many lists, cases and quotes the next token is inside
+    depth: usize,
 }
 
-fn tokens_to_expr(tokens: &mut Vec<String>) -> Result<Expr, String> {
-    if tokens.is_empty() {
-        return Err("No tokens to parse.".to_string())
+impl<'a> Parser<'a> {
+    // From the start of `token` to the end of the last token read.
+    fn span(&self, token: &Token) -> Option<Span> {
+        Some(Span {
+            source: self.shared.clone(),
+            start: token.start,
+            end: self.tokens[self.pos - 1].end,
+        })
+    }
+
+    fn error(&self, message: &str, token: &Token) -> String {
+        let (line, column) = line_column(self.source, token.start);
+        format!("{} at line {}, column {}", message, line, column)
+    }
+
+    fn end_error(&self, message: &str) -> String {
+        let (line, column) = line_column(self.source, self.source.len());
+        format!("{} at line {}, column {}", message, line, column)
     }
-    let token = tokens.pop().unwrap();
-    match token.as_str() {
-        "(" => {
-            let mut l = Vec::new();
-            while tokens.last().unwrap().as_str() != ")" {
-                let expr = tokens_to_expr(tokens);
-                match expr {
-                    Ok(r) => l.push(r),
-                    Err(_) => return expr
+
+    fn expr(&mut self) -> Result<Expr, String> {
+        let token = match self.tokens.get(self.pos) {
+            Some(&token) => token,
+            None => return Err(self.end_error("Unexpected end of input")),
+        };
+        self.pos += 1;
+        match token.kind {
+            TokenKind::OpenParen | TokenKind::OpenBracket | TokenKind::Quote => {
+                if self.depth == MAX_NESTING {
+                    return Err(self.error(&format!("Nested more than {} deep", MAX_NESTING), &token));
                 }
+                self.depth += 1;
+                let expr = self.nested(token);
+                self.depth -= 1;
+                expr
             }
-            tokens.pop().unwrap();
-            Ok(Expr::Exprs(Box::new(remove_spaces(l))))
-        }
-        ")" => {
-            Err("Unexpected )".to_string())
+            TokenKind::CloseParen => Err(self.error("Unexpected )", &token)),
+            TokenKind::CloseBracket => Err(self.error("Unexpected ]", &token)),
+            TokenKind::String => Ok(Expr::Expr(Object::String(unescape(token.text(self.source))))),
+            TokenKind::UnterminatedString => Err(self.error("No end quote", &token)),
+            _ => Ok(Expr::Expr(atomize(token.text(self.source).to_string()))),
         }
-        "[" => {
-            let mut l = Vec::new();
-            while tokens.last().unwrap().as_str() != "]" {
-                let expr = tokens_to_expr(tokens);
-                match expr {
-                    Ok(r) => l.push(r),
-                    Err(_) => return expr
+    }
+
+    // The list, case or quoted form that `token` opens.
+    fn nested(&mut self, token: Token) -> Result<Expr, String> {
+        match token.kind {
+            TokenKind::OpenParen => {
+                let l = try!(self.list(TokenKind::CloseParen, ")"));
+                Ok(Expr::Exprs(Box::new(l), self.span(&token)))
+            }
+            TokenKind::OpenBracket => {
+                let l = try!(self.list(TokenKind::CloseBracket, "]"));
+                if l.len() < 2 {
+                    Err(self.error("Conditional missing body or case declarations", &token))
+                } else {
+                    let ref case = l[0];
+                    let ref body = l[1..];
+                    Ok(Expr::Expr(Object::ConditionalCase(
+                                Box::new((*case).clone()),
+                                body.iter().map(|e| (*e).clone()).collect::<Vec<_>>()
+                    )))
                 }
             }
-            tokens.pop().unwrap();
-            l = remove_spaces(l);
-            if l.len() < 2 {
-                Err("Conditional missing body or case declarations".to_string())
-            } else {
-                let ref case = l[0];
-                let ref body = l[1..];
-                Ok(Expr::Expr(Object::ConditionalCase(
-                            Box::new((*case).clone()),
-                            body.iter().map(|e| (*e).clone()).collect::<Vec<_>>()
-                )))
+            _ => {
+                if self.pos == self.tokens.len() {
+                    return Err(self.end_error("Nothing to quote"));
+                }
+                let quoted = try!(self.expr());
+                Ok(Expr::Exprs(Box::new(vec![Expr::Expr(Object::Symbol("quote".to_string())), quoted]), self.span(&token)))
             }
         }
-        "]" => {
-            Err("Unexpected ]".to_string())
-        }
-        "\"" => {
-            if !tokens.contains(&"\"".to_string()) {
-                return Err("No end quote.".to_string());
-            }
-            let mut s = Vec::new();
-            while tokens.last().unwrap().as_str() != "\"" {
-                s.push(tokens.pop().unwrap());
+    }
+
+    // The exprs up to the closing token, which is consumed.
+    fn list(&mut self, closing: TokenKind, closing_text: &str) -> Result<Vec<Expr>, String> {
+        let mut l = Vec::new();
+        loop {
+            match self.tokens.get(self.pos) {
+                Some(token) if token.kind == closing => {
+                    self.pos += 1;
+                    return Ok(l);
+                }
+                Some(_) => l.push(try!(self.expr())),
+                None => return Err(self.end_error(&format!("Missing {}", closing_text))),
             }
-            tokens.pop().unwrap();
-            s.pop().unwrap();
-            Ok(Expr::Expr(Object::String(s.split_first().unwrap().1.concat())))
-        }
-        _ => {
-            Ok(Expr::Expr(atomize(token)))
         }
     }
 }
 
-fn remove_spaces(l: Vec<Expr>) -> Vec<Expr> {
-    let data = l.iter()
-        .filter(|&x| {
-            if let &Expr::Expr(Object::Symbol(ref s)) = x {
-                if s == " " {
-                    false
-                } else {
-                    true
-                }
-            } else {
-                true
-            }})
-    .map(|x| x.clone())
-        .collect::<Vec<Expr>>();
-    data
+// The contents of a string token, without its quotes. Unknown escapes are
+// kept as written.
+fn unescape(token: &str) -> String {
+    let mut s = String::new();
+    let mut chars = token[1..token.len() - 1].chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            s.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => s.push('\n'),
+            Some('t') => s.push('\t'),
+            Some('r') => s.push('\r'),
+            Some('"') => s.push('"'),
+            Some('\\') => s.push('\\'),
+            Some(other) => {
+                s.push('\\');
+                s.push(other);
+            }
+            None => s.push('\\'),
+        }
+    }
+    s
 }
 
-fn atomize(token: String) -> Object {
+/// The number, boolean, nil or symbol that an atom's text reads as.
+pub fn atomize(token: String) -> Object {
     if token.contains('.') {
         match token.parse::<f64>() {
             Ok(f) => Object::Number(Number::Float(f, token.to_string())),
@@ -145,6 +196,8 @@ fn atomize(token: String) -> Object {
                     Object::Boolean(Boolean::True)
                 } else if &token == "false" {
                     Object::Boolean(Boolean::False)
+                } else if &token == "nil" {
+                    Object::Nil
                 } else {
                     Object::Symbol(token)  
                 }
@@ -164,25 +217,54 @@ fn atomize(token: String) -> Object {
     //"cond"
 //];
 
-fn tokenize(data: &String) -> Vec<String> {
-    // needs to be fixed so that parens inside quotes are not replaced
-    let newdata = data
-        .replace("(", " ( ")
-        .replace(")", " ) ")
-        .replace("\"", " \" ")
-        .replace("[", " [ ")
-        .replace("]", " ] ")
-        .split_whitespace()
-        .map(|s| s.to_string())
-        .collect::<Vec<String>>();
-    let mut spaceddata = Vec::new();
-    for s in newdata.iter() {
-        spaceddata.push(s.clone());
-        spaceddata.push(" ".to_string());
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_str(source: &str) -> Result<Vec<Expr>, String> {
+        parse(&source.to_string())
+    }
+
+    #[test]
+    fn strings() {
+        let exprs = parse_str("\"a\\n\\t\\\"b\\\\\" \"x\\qy\" ; \"not a string\"").unwrap();
+        assert_eq!(exprs,
+                   vec![Expr::Expr(Object::String("a\n\t\"b\\".to_string())),
+                        Expr::Expr(Object::String("x\\qy".to_string()))]);
+        assert_eq!(parse_str("(f \"a  b\" \"c  d\")").unwrap(), parse_str("(f    \"a  b\"\n\"c  d\")").unwrap());
+    }
+
+    #[test]
+    fn spans() {
+        let exprs = parse_named("x.lisp", "(a\n (b 'c))".to_string()).unwrap();
+        let outer = exprs[0].span().unwrap();
+        assert_eq!((outer.text(), outer.position()), ("(a\n (b 'c))", "x.lisp:1:1".to_string()));
+        let inner = match exprs[0] {
+            Expr::Exprs(ref exprs, _) => exprs[1].clone(),
+            _ => panic!("expected a form"),
+        };
+        let span = inner.span().unwrap();
+        assert_eq!((span.text(), span.position()), ("(b 'c)", "x.lisp:2:2".to_string()));
+        // spans are not part of a form's value
+        assert_eq!(inner, parse_str("(b 'c)").unwrap()[0]);
+        assert!(Expr::form(vec![]).span().is_none());
     }
-    spaceddata
-}
 
-fn lines_to_spaces(data: &String) -> String {
-    data.replace("\r\n", " ").replace("\n", " ")
+    #[test]
+    fn errors() {
+        assert_eq!(parse_str("(+ 1\n  2"), Err("Missing ) at line 2, column 4".to_string()));
+        assert_eq!(parse_str("(f x))"), Err("Unexpected ) at line 1, column 6".to_string()));
+        assert_eq!(parse_str("(cond\n  [x])"), Err("Conditional missing body or case declarations at line 2, column 3".to_string()));
+        assert_eq!(parse_str("(print \"abc)"), Err("No end quote at line 1, column 8".to_string()));
+        assert_eq!(parse_str("(f) '"), Err("Nothing to quote at line 1, column 6".to_string()));
+    }
+
+    #[test]
+    fn nesting() {
+        let deep = |n: usize| format!("{}x{}", "(".repeat(n), ")".repeat(n));
+        assert!(parse_str(&deep(MAX_NESTING)).is_ok());
+        assert_eq!(parse_str(&deep(MAX_NESTING + 1)), Err(format!("Nested more than 512 deep at line 1, column {}", MAX_NESTING + 1)));
+        assert!(parse_str(&deep(200000)).is_err());
+        assert!(parse_str(&format!("{}x", "'".repeat(MAX_NESTING + 1))).is_err());
+    }
 }
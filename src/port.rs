@@ -0,0 +1,188 @@
+//! Ports: the sources and sinks lisp code reads from and writes to.
+//!
+//! A port is a handle to a file, one of the standard streams or an in-memory
+//! string. Copies of a port share its state, so closing one closes them all.
+
+use std::fmt;
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufRead, BufReader, BufWriter, Cursor, Write};
+use std::str;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static NEXT_PORT: AtomicUsize = AtomicUsize::new(0);
+
+enum PortState {
+    Stdin,
+    Stdout,
+    Stderr,
+    Input(Box<dyn BufRead + Send>),
+    Output(Box<dyn Write + Send>),
+    StringOutput(String),
+    Closed,
+}
+
+#[derive(Clone)]
+pub struct Port {
+    id: usize,
+    name: String,
+    input: bool,
+    state: Arc<Mutex<PortState>>,
+}
+
+impl Port {
+    fn new(name: &str, input: bool, state: PortState) -> Port {
+        Port {
+            id: NEXT_PORT.fetch_add(1, Ordering::SeqCst),
+            name: name.to_string(),
+            input: input,
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+    pub fn stdin() -> Port {
+        Port::new("stdin", true, PortState::Stdin)
+    }
+    pub fn stdout() -> Port {
+        Port::new("stdout", false, PortState::Stdout)
+    }
+    pub fn stderr() -> Port {
+        Port::new("stderr", false, PortState::Stderr)
+    }
+    pub fn open_input_file(path: &str) -> io::Result<Port> {
+        let file = try!(File::open(path));
+        Ok(Port::new(path, true, PortState::Input(Box::new(BufReader::new(file)))))
+    }
+    /// Creates or truncates the file at `path` for writing.
+    pub fn open_output_file(path: &str) -> io::Result<Port> {
+        let file = try!(File::create(path));
+        Ok(Port::new(path, false, PortState::Output(Box::new(BufWriter::new(file)))))
+    }
+    pub fn input_string(text: &str) -> Port {
+        Port::new("string", true, PortState::Input(Box::new(Cursor::new(text.as_bytes().to_vec()))))
+    }
+    /// A port that collects what is written to it; see `contents`.
+    pub fn output_string() -> Port {
+        Port::new("string", false, PortState::StringOutput(String::new()))
+    }
+    /// The file path, standard stream or "string" the port is over.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    pub fn is_input(&self) -> bool {
+        self.input
+    }
+    pub fn is_output(&self) -> bool {
+        !self.input
+    }
+    /// Reads up to the end of the line, without the line ending. `None` at
+    /// the end of input.
+    pub fn read_line(&self) -> io::Result<Option<String>> {
+        let mut line = String::new();
+        let read = match *self.state.lock().unwrap() {
+            PortState::Stdin => try!(io::stdin().read_line(&mut line)),
+            PortState::Input(ref mut reader) => try!(reader.read_line(&mut line)),
+            ref state => return Err(unusable(state, "an input")),
+        };
+        if read == 0 {
+            return Ok(None);
+        }
+        if line.ends_with('\n') {
+            line.pop();
+            if line.ends_with('\r') {
+                line.pop();
+            }
+        }
+        Ok(Some(line))
+    }
+    /// Reads one utf-8 character. `None` at the end of input.
+    pub fn read_char(&self) -> io::Result<Option<char>> {
+        match *self.state.lock().unwrap() {
+            PortState::Stdin => read_char(&mut io::stdin().lock()),
+            PortState::Input(ref mut reader) => read_char(reader),
+            ref state => Err(unusable(state, "an input")),
+        }
+    }
+    pub fn write_str(&self, s: &str) -> io::Result<()> {
+        match *self.state.lock().unwrap() {
+            PortState::Stdout => {
+                let stdout = io::stdout();
+                let mut stdout = stdout.lock();
+                try!(stdout.write_all(s.as_bytes()));
+                stdout.flush()
+            }
+            PortState::Stderr => io::stderr().write_all(s.as_bytes()),
+            PortState::Output(ref mut writer) => writer.write_all(s.as_bytes()),
+            PortState::StringOutput(ref mut buffer) => {
+                buffer.push_str(s);
+                Ok(())
+            }
+            ref state => Err(unusable(state, "an output")),
+        }
+    }
+    /// Flushes any buffered output and closes the port. Closing a closed port
+    /// does nothing.
+    pub fn close(&self) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let result = match *state {
+            PortState::Output(ref mut writer) => writer.flush(),
+            _ => Ok(()),
+        };
+        *state = PortState::Closed;
+        result
+    }
+    /// What has been written to a string output port so far.
+    pub fn contents(&self) -> Option<String> {
+        match *self.state.lock().unwrap() {
+            PortState::StringOutput(ref buffer) => Some(buffer.clone()),
+            _ => None,
+        }
+    }
+}
+
+fn unusable(state: &PortState, direction: &str) -> io::Error {
+    match *state {
+        PortState::Closed => io::Error::new(io::ErrorKind::Other, "port is closed"),
+        _ => io::Error::new(io::ErrorKind::InvalidInput, format!("not {} port", direction)),
+    }
+}
+
+fn read_char<R: BufRead + ?Sized>(reader: &mut R) -> io::Result<Option<char>> {
+    let mut buf = [0; 4];
+    if try!(reader.read(&mut buf[..1])) == 0 {
+        return Ok(None);
+    }
+    let width = match buf[0] {
+        0x00..=0x7F => 1,
+        0xC0..=0xDF => 2,
+        0xE0..=0xEF => 3,
+        0xF0..=0xF7 => 4,
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "stream did not contain valid UTF-8")),
+    };
+    try!(reader.read_exact(&mut buf[1..width]));
+    match str::from_utf8(&buf[..width]) {
+        Ok(s) => Ok(s.chars().next()),
+        Err(_) => Err(io::Error::new(io::ErrorKind::InvalidData, "stream did not contain valid UTF-8")),
+    }
+}
+
+impl Hash for Port {
+    fn hash<SipHasher>(&self, state: &mut SipHasher) where SipHasher: Hasher {
+        self.id.hash(state);
+    }
+}
+
+impl Eq for Port {}
+
+impl PartialEq for Port {
+    fn eq(&self, other: &Port) -> bool {
+        self.id == other.id
+    }
+}
+
+impl fmt::Debug for Port {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        let direction = if self.input { "input" } else { "output" };
+        write!(f, "Port {{{} {}}}", direction, self.name)
+    }
+}
@@ -1,40 +1,259 @@
 use super::{NAME, VERSION, AUTHOR, INFO};
-use eval::{run_file, run_input};
-use data::*;
-use std::io::{self, Write};
+use rlisp::{Condition, Env, Error, Interpreter, Object};
+use rlisp::data::Inhibit;
+use rlisp::parser::{parse_named, read_file};
+use rlisp::data::Expr;
+use rlisp::lexer::is_complete;
+use rustyline::Editor;
+use rustyline::error::ReadlineError;
+use rustyline::history::DefaultHistory;
+use backtrace::print_backtrace;
+use commands::{Settings, is_command, run_command};
+use helper::LispHelper;
+use style;
+use std::env;
+use std::io::{self, IsTerminal, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
 
-//find some way to detect arrow key presses?
-//have a history?
-pub fn repl(file: Option<&str>) {
+#[cfg(windows)]
+const NEWL: &'static str = "{nl}";
+#[cfg(not(windows))]
+const NEWL: &'static str = "\n";
+
+const RESULT_NAMES: [&'static str; 3] = ["*1", "*2", "*3"];
+const ERROR_NAME: &'static str = "*e";
+// in the home directory
+const HISTORY: &'static str = ".rlisp_history";
+
+pub fn repl(file: Option<&str>, mut interpreter: Interpreter) {
     println!("\r\nStarting REPL for {name} {version}\r\n{author}\r\n{info}\r\n",
              name = NAME,
              version = VERSION,
              author = AUTHOR,
              info = INFO);
 
-    let reader = io::stdin();
-    let stdout = io::stdout();
-    let mut writer = stdout.lock();
-    let mut stdenv = Env::new();
-    //let mut history: Vec<String> = Vec::new();
+    let mut reader = LineReader::new(HISTORY);
     if let Some(filename) = file {
-        run_file(filename, &mut stdenv);
+        run_file(filename, &mut interpreter);
     }
+    interpreter.env_mut().warn_on_redefine = true;
+    clear_results(interpreter.env_mut());
+    let mut settings = Settings::new();
+    // lines are collected until the brackets and strings in them are closed
+    let mut input = String::new();
     loop {
-        //use ncurses to get chars?
-        writer.write(b">>> ").expect("Failed to write line.");
-        writer.flush().expect("Failed to flush stdout.");
-        let mut input = String::new();
-        reader.read_line(&mut input).expect("Failed to read line.");
-        if input != "\n".to_string() {
-            //history.push(input.clone());
-            if let Inhibit::Stop(exit_msg) = run_input(input, &mut stdenv) {
-                if let Some(msg) = exit_msg {
-                    println!("{}", msg);
-                }
+        let prompt = if input.is_empty() { ">>> " } else { "... " };
+        reader.set_names(interpreter.env().variables.keys().cloned());
+        match reader.read_line(prompt) {
+            Ok(line) => {
+                input.push_str(&line);
+                input.push('\n');
+            }
+            // Ctrl-C drops the form being typed
+            Err(ReadlineError::Interrupted) => {
+                input.clear();
+                continue;
+            }
+            Err(ReadlineError::Eof) => break,
+            Err(e) => {
+                println!("Failed to read input: {}", e);
                 break;
             }
-            //println!("{:?}", history);
+        }
+        if input.trim().is_empty() {
+            input.clear();
+            continue;
+        }
+        // commands take the rest of their line, even if it is unfinished lisp
+        if !is_command(&input) && !is_complete(&input) {
+            continue;
+        }
+        let complete = mem::replace(&mut input, String::new());
+        let inhibit = if is_command(&complete) {
+            run_command(&complete, &mut interpreter, &mut settings)
+        } else {
+            run_input(complete, &mut interpreter, settings.print_results)
+        };
+        if let Inhibit::Stop(exit_msg) = inhibit {
+            if let Some(msg) = exit_msg {
+                println!("{}", msg);
+            }
+            break;
+        }
+    }
+    reader.save_history();
+}
+
+// Reads lines with the line editor when stdin is a terminal, and plainly
+// when it is not or the editor cannot start. History is kept in the file
+// named `history` in the home directory.
+pub enum LineReader {
+    Editor(Editor<LispHelper, DefaultHistory>, &'static str),
+    Plain {
+        prompt: bool,
+    },
+}
+
+impl LineReader {
+    pub fn new(history: &'static str) -> LineReader {
+        if !io::stdin().is_terminal() {
+            return LineReader::Plain {
+                prompt: false,
+            };
+        }
+        match Editor::new() {
+            Ok(mut editor) => {
+                editor.set_helper(Some(LispHelper::new()));
+                if let Some(path) = history_path(history) {
+                    // there is no history yet on the first run
+                    let _ = editor.load_history(&path);
+                }
+                LineReader::Editor(editor, history)
+            }
+            Err(e) => {
+                println!("Line editing is unavailable: {}", e);
+                LineReader::Plain {
+                    prompt: true,
+                }
+            }
+        }
+    }
+
+    pub fn read_line(&mut self, prompt: &str) -> Result<String, ReadlineError> {
+        match *self {
+            LineReader::Editor(ref mut editor, _) => {
+                let line = try!(editor.readline(prompt));
+                if !line.trim().is_empty() {
+                    try!(editor.add_history_entry(line.as_str()));
+                }
+                Ok(line)
+            }
+            LineReader::Plain { prompt: show_prompt } => {
+                if show_prompt {
+                    print!("{}", prompt);
+                    try!(io::stdout().flush());
+                }
+                let mut line = String::new();
+                if try!(io::stdin().read_line(&mut line)) == 0 {
+                    return Err(ReadlineError::Eof);
+                }
+                if line.ends_with('\n') {
+                    line.pop();
+                }
+                Ok(line)
+            }
+        }
+    }
+
+    fn set_names<I: Iterator<Item = String>>(&mut self, names: I) {
+        if let LineReader::Editor(ref mut editor, _) = *self {
+            if let Some(helper) = editor.helper_mut() {
+                helper.set_names(names);
+            }
+        }
+    }
+
+    pub fn save_history(&mut self) {
+        if let LineReader::Editor(ref mut editor, history) = *self {
+            if let Some(path) = history_path(history) {
+                if let Err(e) = editor.save_history(&path) {
+                    println!("Failed to save history to {}: {}", path.display(), e);
+                }
+            }
+        }
+    }
+}
+
+fn history_path(name: &str) -> Option<PathBuf> {
+    env::var_os("HOME").or_else(|| env::var_os("USERPROFILE")).map(|home| Path::new(&home).join(name))
+}
+
+pub fn run_file(file: &str, interpreter: &mut Interpreter) {
+    let parsed = read_file(Path::new(file)).map_err(|condition| condition.message);
+    if let Inhibit::Stop(exit_msg) = run_parsed(file, parsed, interpreter, true, false) {
+        if let Some(msg) = exit_msg {
+            println!("{}", msg);
+        }
+    }
+}
+
+/// Evaluates repl input, binding its results and errors to `*1` and `*e`.
+pub fn run_input(input: String, interpreter: &mut Interpreter, print_result: bool) -> Inhibit {
+    let parsed = parse_named("<repl>", input.clone());
+    run_parsed(&input, parsed, interpreter, print_result, true)
+}
+
+/// Binds `*1`, `*2`, `*3` and `*e` to nil, before there are any results.
+pub fn clear_results(env: &mut Env) {
+    for name in RESULT_NAMES.iter().chain(Some(&ERROR_NAME)) {
+        env.variables.insert(name.to_string(), Object::Nil);
+    }
+}
+
+/// Shifts the earlier results along so that `*1` is `result`. Each input
+/// with a value is remembered once, by the value of its last form.
+pub fn remember_result(result: Object, env: &mut Env) {
+    for i in (1..RESULT_NAMES.len()).rev() {
+        let earlier = env.variables.get(RESULT_NAMES[i - 1]).cloned().unwrap_or(Object::Nil);
+        env.variables.insert(RESULT_NAMES[i].to_string(), earlier);
+    }
+    env.variables.insert(RESULT_NAMES[0].to_string(), result);
+}
+
+/// Binds `*e` to the condition of `error`.
+pub fn remember_error(error: Error, env: &mut Env) {
+    let condition = match error {
+        Error::Eval(condition) => condition,
+        Error::Parse(message) => Condition::new("syntax-error", message),
+    };
+    env.variables.insert(ERROR_NAME.to_string(), Object::Condition(condition));
+}
+
+fn run_parsed(original: &str,
+              parsed: Result<Vec<Expr>, String>,
+              interpreter: &mut Interpreter,
+              print_result: bool,
+              remember: bool)
+              -> Inhibit {
+    match parsed {
+        Ok(exprs) => {
+            let mut last = None;
+            for expr_idx in 0..exprs.len() {
+                match interpreter.eval_expr(&exprs[expr_idx]) {
+                    Ok(Some(Object::Exit(s))) => {
+                        return Inhibit::Stop(s);
+                    },
+                    Ok(Some(r)) => {
+                        if expr_idx == exprs.len() - 1 {
+                            if print_result {
+                                println!("{:?}", r);
+                            }
+                            last = Some(r);
+                        }
+                    },
+                    Ok(None) => {},
+                    Err(e) => {
+                        println!("Eval of input: {nl}{nl}{input}{nl} failed with error: {nl}{nl} {e} {nl}", nl = NEWL, input = original, e = style::paint(style::ERROR, &e.to_string()));
+                        if let Error::Eval(ref condition) = e {
+                            print_backtrace(condition);
+                        }
+                        if remember {
+                            remember_error(e, interpreter.env_mut());
+                        }
+                    }
+                }
+            }
+            if let (true, Some(result)) = (remember, last) {
+                remember_result(result, interpreter.env_mut());
+            }
+        },
+        Err(e) => {
+            println!("Parsing of input: {nl}{nl}{input}{nl} failed with error: {nl}{nl} {e} {nl}", nl = NEWL, input = original, e = style::paint(style::ERROR, &e));
+            if remember {
+                remember_error(Error::Parse(e), interpreter.env_mut());
+            }
         }
     }
+    return Inhibit::Continue;
 }
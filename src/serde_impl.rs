@@ -0,0 +1,179 @@
+//! `Serialize`/`Deserialize` for `Object`, enabled by the `serde` feature.
+//!
+//! Lists map to sequences, hash tables to maps, nil to unit, and strings,
+//! numbers and booleans to their native forms. Symbols are written as the
+//! newtype variant `Symbol`, which self-describing formats such as JSON show
+//! as `{"Symbol": "name"}`; a single-entry map of that shape reads back as a
+//! symbol. Functions, conditional cases, error objects and exits have no
+//! serialized form.
+
+use data::*;
+use serde::de::{self, Deserialize, Deserializer, EnumAccess, MapAccess, SeqAccess, VariantAccess, Visitor};
+use serde::ser::{self, Serialize, SerializeMap, SerializeSeq, Serializer};
+use std::collections::HashMap;
+use std::fmt;
+
+impl Serialize for Object {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match *self {
+            Object::Symbol(ref name) => serializer.serialize_newtype_variant("Object", 0, "Symbol", name),
+            Object::String(ref s) => serializer.serialize_str(s),
+            Object::Number(Number::Int(i)) => serializer.serialize_i64(i),
+            Object::Number(Number::Float(f, _)) => serializer.serialize_f64(f),
+            Object::Boolean(ref boolean) => serializer.serialize_bool(boolean.clone().into()),
+            Object::Nil => serializer.serialize_unit(),
+            Object::List(ref elems) => {
+                let mut seq = try!(serializer.serialize_seq(Some(elems.len())));
+                for elem in elems.iter() {
+                    try!(seq.serialize_element(elem));
+                }
+                seq.end()
+            }
+            Object::HashTable(ref table) => {
+                let mut map = try!(serializer.serialize_map(Some(table.0.len())));
+                for (key, value) in table.0.iter() {
+                    try!(map.serialize_entry(key, value));
+                }
+                map.end()
+            }
+            ref other => Err(ser::Error::custom(format!("cannot serialize {:?}", other))),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Object {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Object, D::Error> {
+        deserializer.deserialize_any(ObjectVisitor)
+    }
+}
+
+struct ObjectVisitor;
+
+impl<'de> Visitor<'de> for ObjectVisitor {
+    type Value = Object;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a string, number, boolean, unit, sequence, map or symbol")
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Object, E> {
+        Ok(Object::Nil)
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<Object, E> {
+        Ok(Object::Nil)
+    }
+
+    fn visit_bool<E: de::Error>(self, v: bool) -> Result<Object, E> {
+        Ok(v.into())
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<Object, E> {
+        Ok(Object::Number(Number::Int(v)))
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<Object, E> {
+        if v <= i64::max_value() as u64 {
+            Ok(Object::Number(Number::Int(v as i64)))
+        } else {
+            self.visit_f64(v as f64)
+        }
+    }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> Result<Object, E> {
+        Ok(Object::Number(Number::Float(v, v.to_string())))
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Object, E> {
+        Ok(Object::String(v.to_string()))
+    }
+
+    fn visit_string<E: de::Error>(self, v: String) -> Result<Object, E> {
+        Ok(Object::String(v))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Object, A::Error> {
+        let mut elems = Vec::new();
+        while let Some(elem) = try!(seq.next_element()) {
+            elems.push(elem);
+        }
+        Ok(Object::List(Box::new(elems)))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Object, A::Error> {
+        let mut table = HashMap::new();
+        while let Some((key, value)) = try!(map.next_entry()) {
+            table.insert(key, value);
+        }
+        if table.len() == 1 {
+            if let Some(&Object::String(ref name)) = table.get(&Object::String("Symbol".to_string())) {
+                return Ok(Object::Symbol(name.clone()));
+            }
+        }
+        Ok(Object::HashTable(HashTable(table)))
+    }
+
+    fn visit_enum<A: EnumAccess<'de>>(self, data: A) -> Result<Object, A::Error> {
+        let (variant, value): (String, _) = try!(data.variant());
+        if variant == "Symbol" {
+            Ok(Object::Symbol(try!(value.newtype_variant())))
+        } else {
+            Err(de::Error::unknown_variant(&variant, &["Symbol"]))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate serde_json;
+
+    use data::*;
+    use port::Port;
+    use std::collections::HashMap;
+
+    fn round_trip(object: &Object) -> Object {
+        serde_json::from_str(&serde_json::to_string(object).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn round_trips() {
+        let symbol = Object::Symbol("x".to_string());
+        assert_eq!(serde_json::to_string(&symbol).unwrap(), "{\"Symbol\":\"x\"}");
+        let mut table = HashMap::new();
+        table.insert(Object::String("key".to_string()), Object::List(Box::new(vec![symbol.clone(), Object::Nil])));
+        let objects = vec![symbol,
+                           Object::Nil,
+                           Object::Boolean(Boolean::True),
+                           Object::Boolean(Boolean::False),
+                           Object::Number(Number::Int(-3)),
+                           Object::Number(Number::Float(1.5, "1.5".to_string())),
+                           Object::String("text".to_string()),
+                           Object::List(Box::new(vec![Object::Number(Number::Int(1)), Object::List(Box::new(vec![]))])),
+                           Object::HashTable(HashTable(table))];
+        for object in objects.iter() {
+            assert_eq!(&round_trip(object), object);
+        }
+    }
+
+    #[test]
+    fn unserializable() {
+        let function = Env::new().variables.get("+").cloned().unwrap();
+        for object in [function, Object::Port(Port::stdin())].iter() {
+            let error = serde_json::to_string(object).unwrap_err().to_string();
+            assert!(error.starts_with("cannot serialize"), "{}", error);
+        }
+    }
+
+    #[test]
+    fn symbol_shaped_maps() {
+        // a table that looks like a serialized symbol reads back as one
+        let mut table = HashMap::new();
+        table.insert(Object::String("Symbol".to_string()), Object::String("x".to_string()));
+        assert_eq!(round_trip(&Object::HashTable(HashTable(table.clone()))), Object::Symbol("x".to_string()));
+        // but not with other entries, or a value that is not a string
+        table.insert(Object::String("other".to_string()), Object::Nil);
+        assert_eq!(round_trip(&Object::HashTable(HashTable(table.clone()))), Object::HashTable(HashTable(table)));
+        let parsed: Object = serde_json::from_str("{\"Symbol\": 1}").unwrap();
+        assert!(parsed != Object::Symbol("1".to_string()));
+    }
+}
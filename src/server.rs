@@ -0,0 +1,360 @@
+//! A repl server that editors and scripts send code to.
+//!
+//! Clients connect to a TCP address, or to a unix socket given as
+//! `unix:path`, and exchange JSON objects one per line. Each connection starts
+//! with a session of its own. Sessions are named with random ids, so a
+//! connection can use another's by naming it and several clients can share one
+//! env, but only the connection that made a session can close it, and its
+//! sessions are dropped when it ends. Requests:
+//!
+//! - `{"op": "eval", "code": "(+ 1 2)"}` evaluates each form in `code`
+//! - `{"op": "clone"}` makes a new session, named in the `new-session` reply
+//! - `{"op": "close"}` drops the session
+//!
+//! Any request may give a `session` to act on and an `id`, which is copied
+//! into every reply to it. Replies carry one of `out` (text written while
+//! evaluating a form), `value` (a form's printed value) or `error` (an object
+//! with `kind` and `message`), and the last reply to each request is
+//! `{"status": "done"}`. Evaluation stops at the first error.
+//!
+//! There is no authentication: anyone who can connect can run code as the
+//! server's user, so listen on a loopback address or a private unix socket.
+//! Requests are limited to `MAX_REQUEST` bytes, each form to `EVAL_TIMEOUT`
+//! of evaluation, each connection to `MAX_SESSIONS` sessions and the server
+//! to `MAX_CONNECTIONS` connections at once.
+
+use rlisp::{Condition, Env, Error, HashTable, Interpreter, Object, Port};
+use rlisp::json;
+use rlisp::parser::parse;
+use std::collections::HashMap;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::mem;
+use std::net::TcpListener;
+use std::str;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+#[cfg(unix)]
+use std::os::unix::net::UnixListener;
+
+/// The longest request line read, in bytes; longer ones are skipped.
+pub const MAX_REQUEST: usize = 1 << 20;
+/// How many connections are served at once; each has a thread with a 512MB
+/// stack. Connections past it are refused.
+pub const MAX_CONNECTIONS: usize = 32;
+/// How many sessions a connection may make, the one it starts with included.
+pub const MAX_SESSIONS: usize = 8;
+/// How long a form may evaluate before it is stopped, so that a runaway one
+/// cannot hold its connection's thread for good.
+pub const EVAL_TIMEOUT: Duration = Duration::from_secs(10);
+
+type Session = Arc<Mutex<Interpreter>>;
+
+// The sessions of every connection, and the env new ones are copied from.
+struct Sessions {
+    template: Mutex<Env>,
+    sessions: Mutex<HashMap<String, Session>>,
+    connections: AtomicUsize,
+}
+
+impl Sessions {
+    fn create(&self) -> String {
+        let name = random_id();
+        let mut env = self.template.lock().unwrap().clone();
+        // there is no terminal to read from
+        env.input = Port::input_string("");
+        env.limits.timeout = Some(EVAL_TIMEOUT);
+        self.sessions.lock().unwrap().insert(name.clone(), Arc::new(Mutex::new(Interpreter::with_env(env))));
+        name
+    }
+    fn get(&self, name: &str) -> Option<Session> {
+        self.sessions.lock().unwrap().get(name).cloned()
+    }
+    fn remove(&self, name: &str) -> bool {
+        self.sessions.lock().unwrap().remove(name).is_some()
+    }
+}
+
+// 128 bits that cannot be guessed, from the random keys std seeds each
+// RandomState's hasher with.
+fn random_id() -> String {
+    let half = || RandomState::new().build_hasher().finish();
+    format!("{:016x}{:016x}", half(), half())
+}
+
+// A connection being served and the sessions it made, which are dropped with
+// it however it ends.
+struct Connection<'a> {
+    sessions: &'a Sessions,
+    owned: Vec<String>,
+}
+
+impl<'a> Drop for Connection<'a> {
+    fn drop(&mut self) {
+        for name in self.owned.iter() {
+            self.sessions.remove(name);
+        }
+        self.sessions.connections.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Listens on `address` until the process is stopped. Sessions start as
+/// copies of `env`.
+pub fn serve(address: &str, env: Env) {
+    let sessions = Arc::new(Sessions {
+        template: Mutex::new(env),
+        sessions: Mutex::new(HashMap::new()),
+        connections: AtomicUsize::new(0),
+    });
+    if address.starts_with("unix:") {
+        serve_unix(&address["unix:".len()..], sessions);
+        return;
+    }
+    let listener = match TcpListener::bind(address) {
+        Ok(listener) => listener,
+        Err(e) => {
+            println!("Failed to listen on {}: {}", address, e);
+            return;
+        }
+    };
+    match listener.local_addr() {
+        Ok(local) => println!("Listening on {}", local),
+        Err(_) => println!("Listening on {}", address),
+    }
+    for stream in listener.incoming() {
+        match stream.and_then(|stream| stream.try_clone().map(|reader| (reader, stream))) {
+            Ok((reader, writer)) => spawn_connection(BufReader::new(reader), writer, sessions.clone()),
+            Err(e) => println!("Failed to accept a connection: {}", e),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn serve_unix(path: &str, sessions: Arc<Sessions>) {
+    let listener = match UnixListener::bind(path) {
+        Ok(listener) => listener,
+        Err(e) => {
+            println!("Failed to listen on {}: {}", path, e);
+            return;
+        }
+    };
+    println!("Listening on unix:{}", path);
+    for stream in listener.incoming() {
+        match stream.and_then(|stream| stream.try_clone().map(|reader| (reader, stream))) {
+            Ok((reader, writer)) => spawn_connection(BufReader::new(reader), writer, sessions.clone()),
+            Err(e) => println!("Failed to accept a connection: {}", e),
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn serve_unix(_: &str, _: Arc<Sessions>) {
+    println!("Unix sockets are not supported on this platform");
+}
+
+fn spawn_connection<R, W>(reader: R, mut writer: W, sessions: Arc<Sessions>)
+    where R: BufRead + Send + 'static,
+          W: Write + Send + 'static
+{
+    if sessions.connections.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+        sessions.connections.fetch_sub(1, Ordering::SeqCst);
+        let mut replies = Replies {
+            writer: &mut writer,
+            id: None,
+            session: String::new(),
+        };
+        let _ = replies.error("protocol-error", "Too many connections").and_then(|_| replies.done());
+        return;
+    }
+    let counted = sessions.clone();
+    let spawned = thread::Builder::new().stack_size(super::STACK_SIZE).spawn(move || {
+        let mut connection = Connection {
+            sessions: &sessions,
+            owned: vec![],
+        };
+        if let Err(e) = handle(reader, writer, &mut connection) {
+            println!("Connection failed: {}", e);
+        }
+    });
+    if let Err(e) = spawned {
+        counted.connections.fetch_sub(1, Ordering::SeqCst);
+        println!("Failed to start a connection thread: {}", e);
+    }
+}
+
+fn handle<R: BufRead, W: Write>(mut reader: R, mut writer: W, connection: &mut Connection) -> io::Result<()> {
+    let own_session = connection.sessions.create();
+    connection.owned.push(own_session.clone());
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        try!(reader.by_ref().take(MAX_REQUEST as u64 + 1).read_until(b'\n', &mut line));
+        if line.is_empty() {
+            return Ok(());
+        }
+        let mut replies = Replies {
+            writer: &mut writer,
+            id: None,
+            session: own_session.clone(),
+        };
+        if line.len() > MAX_REQUEST {
+            // skip the rest of it without keeping it
+            while line.last() != Some(&b'\n') {
+                line.clear();
+                if try!(reader.by_ref().take(MAX_REQUEST as u64).read_until(b'\n', &mut line)) == 0 {
+                    break;
+                }
+            }
+            try!(replies.error("protocol-error", &format!("Requests are limited to {} bytes", MAX_REQUEST)));
+            try!(replies.done());
+            continue;
+        }
+        let line = match str::from_utf8(&line) {
+            Ok(line) if line.trim().is_empty() => continue,
+            Ok(line) => line,
+            Err(_) => {
+                try!(replies.error("protocol-error", "Requests must be UTF-8"));
+                try!(replies.done());
+                continue;
+            }
+        };
+        let request = match json::parse(line) {
+            Ok(Object::HashTable(request)) => request,
+            Ok(_) => {
+                try!(replies.error("protocol-error", "Requests must be JSON objects"));
+                try!(replies.done());
+                continue;
+            }
+            Err(e) => {
+                try!(replies.error("protocol-error", &format!("Invalid JSON: {}", e)));
+                try!(replies.done());
+                continue;
+            }
+        };
+        replies.id = field(&request, "id").cloned();
+        if let Some(session) = field(&request, "session") {
+            match *session {
+                Object::String(ref session) => replies.session = session.clone(),
+                _ => {
+                    try!(replies.error("protocol-error", "session must be a string"));
+                    try!(replies.done());
+                    continue;
+                }
+            }
+        }
+        try!(respond(&request, &mut replies, connection));
+        try!(replies.done());
+    }
+}
+
+fn respond<W: Write>(request: &HashTable, replies: &mut Replies<W>, connection: &mut Connection) -> io::Result<()> {
+    let sessions = connection.sessions;
+    let op = match field(request, "op") {
+        Some(&Object::String(ref op)) => op.clone(),
+        _ => return replies.error("protocol-error", "Requests need an op"),
+    };
+    match op.as_str() {
+        "clone" => {
+            if connection.owned.len() >= MAX_SESSIONS {
+                return replies.error("protocol-error", &format!("A connection may make at most {} sessions", MAX_SESSIONS));
+            }
+            let name = sessions.create();
+            connection.owned.push(name.clone());
+            replies.send(vec![("new-session", Object::String(name))])
+        }
+        "close" => {
+            match connection.owned.iter().position(|name| *name == replies.session) {
+                Some(i) => {
+                    sessions.remove(&connection.owned.remove(i));
+                    Ok(())
+                }
+                None if sessions.get(&replies.session).is_some() => {
+                    replies.error("protocol-error", &format!("Session {} belongs to another connection", replies.session))
+                }
+                None => replies.error("protocol-error", &format!("No session {}", replies.session)),
+            }
+        }
+        "eval" => {
+            let code = match field(request, "code") {
+                Some(&Object::String(ref code)) => code.clone(),
+                _ => return replies.error("protocol-error", "eval needs the code to evaluate"),
+            };
+            match sessions.get(&replies.session) {
+                Some(session) => eval(&code, &mut session.lock().unwrap(), replies),
+                None => replies.error("protocol-error", &format!("No session {}", replies.session)),
+            }
+        }
+        _ => replies.error("protocol-error", &format!("Unknown op {}", op)),
+    }
+}
+
+fn eval<W: Write>(code: &str, interpreter: &mut Interpreter, replies: &mut Replies<W>) -> io::Result<()> {
+    let exprs = match parse(&code.to_string()) {
+        Ok(exprs) => exprs,
+        Err(message) => return replies.error("syntax-error", &message),
+    };
+    for expr in exprs {
+        let output = Port::output_string();
+        let previous = mem::replace(&mut interpreter.env_mut().output, output.clone());
+        let result = interpreter.eval_expr(&expr);
+        interpreter.env_mut().output = previous;
+        match output.contents() {
+            Some(ref out) if !out.is_empty() => try!(replies.send(vec![("out", Object::String(out.clone()))])),
+            _ => {}
+        }
+        match result {
+            Ok(Some(Object::Exit(_))) => return replies.error("error", "exit is not available in a server session"),
+            Ok(Some(value)) => try!(replies.send(vec![("value", Object::String(format!("{:?}", value)))])),
+            Ok(None) => {}
+            Err(Error::Eval(condition)) => return replies.condition(&condition),
+            Err(Error::Parse(message)) => return replies.error("syntax-error", &message),
+        }
+    }
+    Ok(())
+}
+
+fn field<'a>(table: &'a HashTable, name: &str) -> Option<&'a Object> {
+    table.0.get(&Object::String(name.to_string()))
+}
+
+// Writes the replies to one request.
+struct Replies<'a, W: Write + 'a> {
+    writer: &'a mut W,
+    id: Option<Object>,
+    session: String,
+}
+
+impl<'a, W: Write> Replies<'a, W> {
+    fn send(&mut self, fields: Vec<(&str, Object)>) -> io::Result<()> {
+        let mut reply = HashMap::new();
+        if let Some(ref id) = self.id {
+            reply.insert(Object::String("id".to_string()), id.clone());
+        }
+        // refused connections have no session
+        if !self.session.is_empty() {
+            reply.insert(Object::String("session".to_string()), Object::String(self.session.clone()));
+        }
+        for (name, value) in fields {
+            reply.insert(Object::String(name.to_string()), value);
+        }
+        let text = try!(json::to_string(&Object::HashTable(HashTable(reply)), None)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)));
+        try!(writeln!(self.writer, "{}", text));
+        self.writer.flush()
+    }
+    fn error(&mut self, kind: &str, message: &str) -> io::Result<()> {
+        self.condition(&Condition::new(kind, message.to_string()))
+    }
+    fn condition(&mut self, condition: &Condition) -> io::Result<()> {
+        let mut error = HashMap::new();
+        error.insert(Object::String("kind".to_string()), Object::String(condition.kind.clone()));
+        error.insert(Object::String("message".to_string()), Object::String(condition.message.clone()));
+        self.send(vec![("error", Object::HashTable(HashTable(error)))])
+    }
+    fn done(&mut self) -> io::Result<()> {
+        self.send(vec![("status", Object::String("done".to_string()))])
+    }
+}
@@ -1,59 +1,115 @@
 #![allow(dead_code)]
 use data::*;
 use std::sync::Arc;
-use eval::Eval;
+use eval::{Eval, apply_function, eval_form};
+use parser::{parse, read_file};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::collections::HashMap;
+use json;
+use debug;
+use module;
+use port::Port;
+use convert::from_arg;
+use std::io;
+use std::mem;
+use std::path::Path;
 use std::ops::{AddAssign, DivAssign, MulAssign, SubAssign, RemAssign};
 
 macro_rules! generate_base_fn {
-    ($fnname:expr, $name:ident) => {
-        ($fnname, Function {procedure: Arc::new(LispFn::Builtin(BuiltinFn::new($fnname, $name)))})
+    ($fnname:expr, $name:ident, $arity:expr, $doc:expr) => {
+        ($fnname, Function::builtin(BuiltinFn::new($fnname, $name).with_arity($arity).with_doc($doc)))
     }
 }
 
+macro_rules! generate_lisp_fn {
+    ($fnname:expr, $name:ident) => {($fnname, Function::builtin($name()))}
+}
+
 macro_rules! generate_normal_base_fn {
-    ($name:ident) => {generate_base_fn!(stringify!($name), $name)}
+    ($name:ident, $arity:expr, $doc:expr) => {generate_base_fn!(stringify!($name), $name, $arity, $doc)}
 }
 
 lazy_static! {
-    pub static ref BASE_FUNCTIONS: [(&'static str, Function); 14] = [
-        generate_normal_base_fn!(list),
-        generate_normal_base_fn!(cons),
-        generate_normal_base_fn!(print),
-        generate_normal_base_fn!(exit),
-        generate_normal_base_fn!(cond),
-        generate_base_fn!("=", equals),
-        generate_normal_base_fn!(and),
-        generate_normal_base_fn!(or),
-        generate_normal_base_fn!(not),
-        generate_base_fn!("+", add),
-        generate_base_fn!("-", sub),
-        generate_base_fn!("*", mul),
-        generate_base_fn!("/", div),
-        generate_base_fn!("%", rem),
+    pub static ref BASE_FUNCTIONS: [(&'static str, Function); 48] = [
+        generate_normal_base_fn!(list, Arity::Any, "(list items...) makes a list of its args."),
+        generate_normal_base_fn!(cons, Arity::Exactly(2), "(cons a b) joins an item onto either end of a list, or makes a pair."),
+        generate_normal_base_fn!(print, Arity::Exactly(1), "(print item) prints an item."),
+        generate_normal_base_fn!(exit, Arity::Range(0, 1), "(exit [message]) stops the interpreter."),
+        generate_base_fn!("break", break_here, Arity::Exactly(0), "(break) pauses in the debugger, if one is attached; see --debug."),
+        generate_normal_base_fn!(cond, Arity::AtLeast(2), "(cond [case body...] ... [else body...]) evaluates the body of the first true case."),
+        generate_base_fn!("=", equals, Arity::AtLeast(1), "(= items...) is true if all items are equal."),
+        generate_normal_base_fn!(and, Arity::Any, "(and booleans...) is true if all booleans are true."),
+        generate_normal_base_fn!(or, Arity::Any, "(or booleans...) is true if any boolean is true."),
+        generate_normal_base_fn!(not, Arity::Exactly(1), "(not boolean) negates a boolean."),
+        generate_base_fn!("+", add, Arity::AtLeast(2), "(+ numbers...) adds numbers."),
+        generate_base_fn!("-", sub, Arity::AtLeast(2), "(- numbers...) subtracts the rest of the numbers from the first."),
+        generate_base_fn!("*", mul, Arity::AtLeast(2), "(* numbers...) multiplies numbers."),
+        generate_base_fn!("/", div, Arity::AtLeast(2), "(/ numbers...) divides the first number by the rest."),
+        generate_base_fn!("%", rem, Arity::AtLeast(2), "(% numbers...) takes the remainder of the first number by the rest."),
+        generate_normal_base_fn!(raise, Arity::Exactly(1), "(raise obj) signals obj, or an error object carrying it."),
+        generate_normal_base_fn!(error, Arity::AtLeast(1), "(error message irritants...) signals an error object."),
+        generate_base_fn!("error-object?", is_error_object, Arity::Exactly(1), "(error-object? obj) is true if obj is an error object."),
+        generate_base_fn!("error-object-kind", error_object_kind, Arity::Exactly(1), "(error-object-kind e) is the kind of error, such as \"type-error\"."),
+        generate_base_fn!("error-object-message", error_object_message, Arity::Exactly(1), "(error-object-message e) is the message of an error."),
+        generate_base_fn!("error-object-irritants", error_object_irritants, Arity::Exactly(1), "(error-object-irritants e) is the list of objects attached to an error."),
+        generate_base_fn!("error-object-backtrace", error_object_backtrace, Arity::Exactly(1), "(error-object-backtrace e) is the list of calls e unwound through, innermost first, each as (name call)."),
+        generate_base_fn!("call-with-current-continuation", call_cc, Arity::Exactly(1), "(call-with-current-continuation f) calls f with an escape-only continuation."),
+        generate_base_fn!("call/cc", call_cc, Arity::Exactly(1), "(call/cc f) calls f with an escape-only continuation."),
+        generate_base_fn!("dynamic-wind", dynamic_wind, Arity::Exactly(3), "(dynamic-wind before thunk after) calls thunk between before and after, running after even on escape."),
+        generate_base_fn!("hash-table", hash_table, Arity::Any, "(hash-table key value ...) makes a hash table from pairs of args."),
+        generate_lisp_fn!("hash-ref", hash_ref),
+        generate_lisp_fn!("hash-set", hash_set),
+        generate_lisp_fn!("hash-keys", hash_keys),
+        generate_lisp_fn!("json-parse", json_parse),
+        generate_lisp_fn!("json->string", json_to_string),
+        generate_lisp_fn!("open-input-file", open_input_file),
+        generate_lisp_fn!("open-output-file", open_output_file),
+        generate_lisp_fn!("open-input-string", open_input_string),
+        generate_lisp_fn!("close-port", close_port),
+        generate_base_fn!("current-input-port", current_input_port, Arity::Exactly(0), "(current-input-port) is the port read from when none is given."),
+        generate_base_fn!("current-output-port", current_output_port, Arity::Exactly(0), "(current-output-port) is the port written to when none is given."),
+        generate_base_fn!("current-error-port", current_error_port, Arity::Exactly(0), "(current-error-port) is a port to stderr."),
+        generate_base_fn!("read-line", read_line, Arity::Range(0, 1), "(read-line [port]) reads a line without its line ending, or nil at the end of input."),
+        generate_base_fn!("read-char", read_char, Arity::Range(0, 1), "(read-char [port]) reads a character as a string, or nil at the end of input."),
+        generate_base_fn!("write-string", write_string, Arity::Range(1, 2), "(write-string string [port]) writes string as is."),
+        generate_base_fn!("with-output-to-string", with_output_to_string, Arity::Exactly(1), "(with-output-to-string thunk) calls thunk, collecting what it writes to the current output port into a string."),
+        generate_base_fn!("read", read, Arity::Range(0, 1), "(read [string-or-port]) reads one form as data, or nil at the end of input."),
+        generate_normal_base_fn!(eval, Arity::Range(1, 2), "(eval form [environment]) evaluates form, in environment if given."),
+        generate_normal_base_fn!(load, Arity::Exactly(1), "(load path) evaluates the file at path in the current environment."),
+        generate_normal_base_fn!(require, Arity::Exactly(1), "(require name) loads the module name once, binding its exports as name/export."),
+        generate_base_fn!("standard-environment", standard_environment, Arity::Exactly(0), "(standard-environment) makes an environment with only the builtins."),
+        generate_base_fn!("call-with-output-file", call_with_output_file, Arity::Exactly(2), "(call-with-output-file path f) calls f with a port writing to path, closing it afterwards."),
     ];
 }
 
 macro_rules! gen_math_func {
-    ( $name:ident, $op:ident ) => {
-        fn $name(args: Vec<Object>, _: &mut Env) -> Result<Option<Object>, String> {
+    ( $name:ident, $op:ident, $checked_op:ident ) => {
+        fn $name(args: Vec<Object>, _: &mut Env) -> Result<Option<Object>, Condition> {
             if args.iter().all(|o| {if let &Object::Number(_) = o {true} else {false}}) && args.len() > 1 {
                 let mut base = args[0].unwrap_number().unwrap().to_owned();
                 for arg in args.iter().skip(1).map(|o| (*o.unwrap_number().unwrap()).clone()) {
+                    // integer ops that would overflow or divide by zero panic
+                    if let (&Number::Int(i), &Number::Int(oi)) = (&base, &arg) {
+                        if i.$checked_op(oi).is_none() {
+                            let message = if oi == 0 { "Division by zero" } else { "Integer overflow" };
+                            return Err(Condition::with_irritants("arithmetic-error", message.to_string(), args.clone()));
+                        }
+                    }
                     base.$op(arg);
                 }
                 Ok(Some(Object::Number(base)))
             } else {
-                Err(format!("Invalid or too little args for function {}", stringify!($name)))
+                Err(Condition::new("type-error", format!("Invalid or too little args for function {}", stringify!($name))))
             }
         }
     }
 }
 
-gen_math_func!(add, add_assign);
-gen_math_func!(sub, sub_assign);
-gen_math_func!(mul, mul_assign);
-gen_math_func!(div, div_assign);
-gen_math_func!(rem, rem_assign);
+gen_math_func!(add, add_assign, checked_add);
+gen_math_func!(sub, sub_assign, checked_sub);
+gen_math_func!(mul, mul_assign, checked_mul);
+gen_math_func!(div, div_assign, checked_div);
+gen_math_func!(rem, rem_assign, checked_rem);
 
 macro_rules! x_only {
     ( $item_ident:ident; $qualifier:ident; $args:expr; $operation:block ) => {
@@ -61,7 +117,7 @@ macro_rules! x_only {
             $item_ident = $args.iter().map(|obj| obj.unwrap_boolean().unwrap()).collect::<Vec<_>>();
             $operation
         } else {
-            Err(format!("Function only usable on {} items", stringify!($qualifier)))
+            Err(Condition::new("type-error", format!("Function only usable on {} items", stringify!($qualifier))))
         }
     }
 }
@@ -76,7 +132,7 @@ fn all_boolean(args: &[Object]) -> bool {
     })
 }
 
-fn and(args: Vec<Object>, _: &mut Env) -> Result<Option<Object>, String> {
+fn and(args: Vec<Object>, _: &mut Env) -> Result<Option<Object>, Condition> {
     let items;
     x_only!(items; all_boolean; args;
             {
@@ -88,7 +144,7 @@ fn and(args: Vec<Object>, _: &mut Env) -> Result<Option<Object>, String> {
             })
 }
 
-fn or(args: Vec<Object>, _: &mut Env) -> Result<Option<Object>, String> {
+fn or(args: Vec<Object>, _: &mut Env) -> Result<Option<Object>, Condition> {
     let items;
     x_only!(items; all_boolean; args;
             {
@@ -100,12 +156,12 @@ fn or(args: Vec<Object>, _: &mut Env) -> Result<Option<Object>, String> {
             })
 }
 
-fn not(args: Vec<Object>, _: &mut Env) -> Result<Option<Object>, String> {
+fn not(args: Vec<Object>, _: &mut Env) -> Result<Option<Object>, Condition> {
     let items;
     x_only!(items; all_boolean; args;
             {
                 if items.len() != 1 {
-                    Err("Invalid number of args for logical not".to_string())
+                    Err(Condition::new("arity-error", "Invalid number of args for logical not".to_string()))
                 } else {
                     let notted: bool = (*items[0]).clone().into();
                     Ok(Some((!notted).into()))
@@ -113,29 +169,29 @@ fn not(args: Vec<Object>, _: &mut Env) -> Result<Option<Object>, String> {
             })
 }
 
-//fn xor(args: Vec<Object>, _: &mut Env) -> Result<Option<Object>, String> {
+//fn xor(args: Vec<Object>, _: &mut Env) -> Result<Option<Object>, Condition> {
 //unimplemented!()
 //}
 
-//fn bit_and(args: Vec<Object>, _: &mut Env) -> Result<Option<Object>, String> {
+//fn bit_and(args: Vec<Object>, _: &mut Env) -> Result<Option<Object>, Condition> {
 //unimplemented!()
 //}
 
-//fn bit_or(args: Vec<Object>, _: &mut Env) -> Result<Option<Object>, String> {
+//fn bit_or(args: Vec<Object>, _: &mut Env) -> Result<Option<Object>, Condition> {
 //unimplemented!()
 //}
 
-//fn bit_not(args: Vec<Object>, _: &mut Env) -> Result<Option<Object>, String> {
+//fn bit_not(args: Vec<Object>, _: &mut Env) -> Result<Option<Object>, Condition> {
 //unimplemented!()
 //}
 
-//fn bit_xor(args: Vec<Object>, _: &mut Env) -> Result<Option<Object>, String> {
+//fn bit_xor(args: Vec<Object>, _: &mut Env) -> Result<Option<Object>, Condition> {
 //unimplemented!()
 //}
 
-fn equals(args: Vec<Object>, _: &mut Env) -> Result<Option<Object>, String> {
+fn equals(args: Vec<Object>, _: &mut Env) -> Result<Option<Object>, Condition> {
     if args.len() == 0 {
-        Err("Not enough args for =".to_string())
+        Err(Condition::new("arity-error", "Not enough args for =".to_string()))
     } else {
         let ref first = args[0];
         if args.iter().all(|o| o == first) {
@@ -146,9 +202,14 @@ fn equals(args: Vec<Object>, _: &mut Env) -> Result<Option<Object>, String> {
     }
 }
 
-fn cond(args: Vec<Object>, env: &mut Env) -> Result<Option<Object>, String> {
+fn break_here(_: Vec<Object>, env: &mut Env) -> Result<Option<Object>, Condition> {
+    debug::pause_at(None, env);
+    Ok(None)
+}
+
+fn cond(args: Vec<Object>, env: &mut Env) -> Result<Option<Object>, Condition> {
     if args.len() < 2 {
-        Err("Not enough args for cond".to_string())
+        Err(Condition::new("arity-error", "Not enough args for cond".to_string()))
     } else if args.iter().all(|e| {
         match *e {
             Object::ConditionalCase(_, _) => true,
@@ -177,38 +238,38 @@ fn cond(args: Vec<Object>, env: &mut Env) -> Result<Option<Object>, String> {
                             return (*body).eval(env);
                         }
                     } else {
-                        return Err(format!("Case {:?} does not return a boolean", case));
+                        return Err(Condition::new("type-error", format!("Case {:?} does not return a boolean", case)));
                     }
                 }
             }
             if let Object::ConditionalCase(_, ref body) = args[args.len() - 1] {
                 (*body).eval(env)
             } else {
-                Err("Conditional evaluation error".to_string())
+                Err(Condition::new("syntax-error", "Conditional evaluation error".to_string()))
             }
         } else {
-            Err("Invalid final arg for cond".to_string())
+            Err(Condition::new("syntax-error", "Invalid final arg for cond".to_string()))
         }
     } else {
-        Err("Not all arguments of cond are conditional cases".to_string())
+        Err(Condition::new("syntax-error", "Not all arguments of cond are conditional cases".to_string()))
     }
 }
 
-fn list(args: Vec<Object>, _: &mut Env) -> Result<Option<Object>, String> {
+fn list(args: Vec<Object>, _: &mut Env) -> Result<Option<Object>, Condition> {
     Ok(Some(Object::List(Box::new(args))))
 }
 
-fn cons(args: Vec<Object>, _: &mut Env) -> Result<Option<Object>, String> {
+fn cons(args: Vec<Object>, _: &mut Env) -> Result<Option<Object>, Condition> {
     if !(args.len() == 2) {
         //invalid arg number
-        Err("Invalid number of arguments for cons.".to_string())
+        Err(Condition::new("arity-error", "Invalid number of arguments for cons.".to_string()))
     } else {
         let first = args.first().unwrap().clone();
         let last = args.last().unwrap().clone();
         if let Object::List(elems) = first {
             //list is in the head position; append the element
             if let Object::List(_) = last {
-                Err(format!("Cannot cons two lists."))
+                Err(Condition::new("type-error", "Cannot cons two lists.".to_string()))
             } else {
                 let mut tmpvec = *elems.clone();
                 tmpvec.push(last.clone());
@@ -229,18 +290,18 @@ fn cons(args: Vec<Object>, _: &mut Env) -> Result<Option<Object>, String> {
     }
 }
 
-fn print(args: Vec<Object>, _: &mut Env) -> Result<Option<Object>, String> {
+fn print(args: Vec<Object>, env: &mut Env) -> Result<Option<Object>, Condition> {
     if args.len() != 1 {
-        Err("Invalid number of args for print".to_string())
+        Err(Condition::new("arity-error", "Invalid number of args for print".to_string()))
     } else {
-        println!("{:?}", args[0]);
+        try!(env.output.write_str(&format!("{:?}\n", args[0])).map_err(|e| io_error(e, Object::Port(env.output.clone()))));
         Ok(None)
     }
 }
 
-fn exit(args: Vec<Object>, _: &mut Env) -> Result<Option<Object>, String> {
+fn exit(args: Vec<Object>, _: &mut Env) -> Result<Option<Object>, Condition> {
     if args.len() > 1 {
-        Err("Invalid number of arguments for exit.".to_string())
+        Err(Condition::new("arity-error", "Invalid number of arguments for exit.".to_string()))
     } else if args.len() == 1 {
         // PLACEHOLDER
         Ok(Some(Object::Exit(Some(format!("{:?}", args[0])))))
@@ -248,3 +309,365 @@ fn exit(args: Vec<Object>, _: &mut Env) -> Result<Option<Object>, String> {
         Ok(Some(Object::Exit(None)))
     }
 }
+
+fn raise(args: Vec<Object>, _: &mut Env) -> Result<Option<Object>, Condition> {
+    if args.len() != 1 {
+        Err(Condition::new("arity-error", "Invalid number of args for raise".to_string()))
+    } else {
+        match args[0] {
+            Object::Condition(ref condition) => Err(condition.clone()),
+            ref other => Err(Condition::with_irritants("raise", "Uncaught raise".to_string(), vec![other.clone()])),
+        }
+    }
+}
+
+fn error(args: Vec<Object>, _: &mut Env) -> Result<Option<Object>, Condition> {
+    match args.split_first() {
+        Some((&Object::String(ref message), irritants)) => {
+            Err(Condition::with_irritants("error", message.clone(), irritants.to_vec()))
+        }
+        _ => Err(Condition::new("type-error", "error requires a message string".to_string())),
+    }
+}
+
+fn is_error_object(args: Vec<Object>, _: &mut Env) -> Result<Option<Object>, Condition> {
+    if args.len() != 1 {
+        Err(Condition::new("arity-error", "Invalid number of args for error-object?".to_string()))
+    } else if let Object::Condition(_) = args[0] {
+        Ok(Some(true.into()))
+    } else {
+        Ok(Some(false.into()))
+    }
+}
+
+fn unwrap_condition<'a>(args: &'a [Object], fnname: &str) -> Result<&'a Condition, Condition> {
+    if args.len() != 1 {
+        Err(Condition::new("arity-error", format!("Invalid number of args for {}", fnname)))
+    } else if let Object::Condition(ref condition) = args[0] {
+        Ok(condition)
+    } else {
+        Err(Condition::new("type-error", format!("{} requires an error object", fnname)))
+    }
+}
+
+fn error_object_kind(args: Vec<Object>, _: &mut Env) -> Result<Option<Object>, Condition> {
+    let condition = try!(unwrap_condition(&args, "error-object-kind"));
+    Ok(Some(Object::String(condition.kind.clone())))
+}
+
+fn error_object_message(args: Vec<Object>, _: &mut Env) -> Result<Option<Object>, Condition> {
+    let condition = try!(unwrap_condition(&args, "error-object-message"));
+    Ok(Some(Object::String(condition.message.clone())))
+}
+
+fn error_object_irritants(args: Vec<Object>, _: &mut Env) -> Result<Option<Object>, Condition> {
+    let condition = try!(unwrap_condition(&args, "error-object-irritants"));
+    Ok(Some(Object::List(Box::new(condition.irritants.clone()))))
+}
+
+fn error_object_backtrace(args: Vec<Object>, _: &mut Env) -> Result<Option<Object>, Condition> {
+    let condition = try!(unwrap_condition(&args, "error-object-backtrace"));
+    let calls = condition.backtrace
+        .iter()
+        .map(|site| Object::List(Box::new(vec![Object::String(site.function.clone()), site.call.to_object()])))
+        .collect();
+    Ok(Some(Object::List(Box::new(calls))))
+}
+
+static NEXT_CONTINUATION: AtomicUsize = AtomicUsize::new(0);
+
+fn unwrap_functions(args: Vec<Object>, fnname: &str, count: usize) -> Result<Vec<Function>, Condition> {
+    if args.len() != count {
+        return Err(Condition::new("arity-error", format!("Invalid number of args for {}", fnname)));
+    }
+    let mut functions = Vec::new();
+    for arg in args {
+        if let Object::Function(function) = arg {
+            functions.push(function);
+        } else {
+            return Err(Condition::new("type-error", format!("{} requires function args", fnname)));
+        }
+    }
+    Ok(functions)
+}
+
+// Escape-only: the continuation can be used to return early from within the
+// call, but not to re-enter it once call/cc has returned.
+fn call_cc(args: Vec<Object>, env: &mut Env) -> Result<Option<Object>, Condition> {
+    let receiver = try!(unwrap_functions(args, "call/cc", 1)).remove(0);
+    let id = NEXT_CONTINUATION.fetch_add(1, Ordering::SeqCst);
+    let continuation = Function {
+        procedure: Arc::new(LispFn::Continuation(id)),
+        namespace: None,
+    };
+    match apply_function(receiver, vec![Object::Function(continuation)], env) {
+        Err(condition) => {
+            match condition.escaped_value(id) {
+                Some(value) => Ok(value),
+                None => Err(condition),
+            }
+        }
+        result => result,
+    }
+}
+
+fn dynamic_wind(args: Vec<Object>, env: &mut Env) -> Result<Option<Object>, Condition> {
+    let mut thunks = try!(unwrap_functions(args, "dynamic-wind", 3)).into_iter();
+    let (before, thunk, after) = (thunks.next().unwrap(), thunks.next().unwrap(), thunks.next().unwrap());
+    try!(apply_function(before, Vec::new(), env));
+    let result = apply_function(thunk, Vec::new(), env);
+    try!(apply_function(after, Vec::new(), env));
+    result
+}
+
+fn hash_table(args: Vec<Object>, _: &mut Env) -> Result<Option<Object>, Condition> {
+    if args.len() % 2 != 0 {
+        return Err(Condition::new("arity-error", "hash-table takes pairs of keys and values".to_string()));
+    }
+    let mut table = HashMap::new();
+    let mut args = args.into_iter();
+    while let (Some(key), Some(value)) = (args.next(), args.next()) {
+        table.insert(key, value);
+    }
+    Ok(Some(Object::HashTable(HashTable(table))))
+}
+
+lisp_fn! {
+    /// (hash-ref table key [default]) looks up key, falling back to default.
+    fn hash_ref["hash-ref"](table: HashMap<Object, Object>, key: Object, default: Option<Object>) -> Result<Object, Condition> {
+        match table.get(&key).cloned().or(default) {
+            Some(value) => Ok(value),
+            None => Err(Condition::with_irritants("key-error", "Key not in hash table".to_string(), vec![key])),
+        }
+    }
+}
+
+lisp_fn! {
+    /// (hash-set table key value) is a copy of table with key set to value.
+    fn hash_set["hash-set"](table: HashMap<Object, Object>, key: Object, value: Object) -> HashMap<Object, Object> {
+        let mut table = table;
+        table.insert(key, value);
+        table
+    }
+}
+
+lisp_fn! {
+    /// (hash-keys table) lists the keys of table.
+    fn hash_keys["hash-keys"](table: HashMap<Object, Object>) -> Vec<Object> {
+        table.into_iter().map(|(key, _)| key).collect()
+    }
+}
+
+lisp_fn! {
+    /// (json-parse text) reads JSON text into hash tables, lists, strings,
+    /// numbers, booleans and nil.
+    fn json_parse["json-parse"](text: String) -> Result<Object, Condition> {
+        json::parse(&text).map_err(|e| {
+            Condition::with_irritants("json-error", e.to_string(), vec![Object::Number(Number::Int(e.offset as i64))])
+        })
+    }
+}
+
+lisp_fn! {
+    /// (json->string obj [pretty]) writes obj as JSON; pretty is true for two
+    /// space indentation or a number of spaces up to 10.
+    fn json_to_string["json->string"](object: Object, pretty: Option<Object>) -> Result<String, Condition> {
+        let indent = match pretty {
+            None | Some(Object::Boolean(Boolean::False)) => None,
+            Some(Object::Boolean(Boolean::True)) => Some(2),
+            Some(Object::Number(Number::Int(n))) if n > json::MAX_INDENT as i64 => {
+                return Err(Condition::with_irritants("domain-error", format!("json->string indents by at most {} spaces", json::MAX_INDENT), vec![Object::Number(Number::Int(n))]));
+            }
+            Some(Object::Number(Number::Int(n))) if n >= 0 => Some(n as usize),
+            Some(other) => return Err(Condition::with_irritants("type-error", "json->string expects a boolean or indent for pretty".to_string(), vec![other])),
+        };
+        json::to_string(&object, indent).map_err(|e| Condition::new("json-error", e))
+    }
+}
+
+fn io_error(error: io::Error, irritant: Object) -> Condition {
+    Condition::with_irritants("io-error", error.to_string(), vec![irritant])
+}
+
+fn open_error(error: io::Error, path: String) -> Condition {
+    Condition::with_irritants("io-error", format!("Could not open {:?}: {}", path, error), vec![Object::String(path)])
+}
+
+// The port given as args[index], or default if it was left off.
+fn optional_port(args: &[Object], index: usize, fnname: &str, default: &Port) -> Result<Port, Condition> {
+    let port: Option<Port> = try!(from_arg(args.get(index).cloned(), fnname, "port"));
+    Ok(port.unwrap_or_else(|| default.clone()))
+}
+
+lisp_fn! {
+    /// (open-input-file path) opens the file at path for reading.
+    fn open_input_file["open-input-file"](path: String) -> Result<Port, Condition> {
+        Port::open_input_file(&path).map_err(|e| open_error(e, path))
+    }
+}
+
+lisp_fn! {
+    /// (open-output-file path) creates or truncates the file at path for writing.
+    fn open_output_file["open-output-file"](path: String) -> Result<Port, Condition> {
+        Port::open_output_file(&path).map_err(|e| open_error(e, path))
+    }
+}
+
+lisp_fn! {
+    /// (open-input-string text) makes a port that reads from text.
+    fn open_input_string["open-input-string"](text: String) -> Port {
+        Port::input_string(&text)
+    }
+}
+
+lisp_fn! {
+    /// (close-port port) flushes and closes port.
+    fn close_port["close-port"](port: Port) -> Result<(), Condition> {
+        port.close().map_err(|e| io_error(e, Object::Port(port)))
+    }
+}
+
+fn current_input_port(_: Vec<Object>, env: &mut Env) -> Result<Option<Object>, Condition> {
+    Ok(Some(Object::Port(env.input.clone())))
+}
+
+fn current_output_port(_: Vec<Object>, env: &mut Env) -> Result<Option<Object>, Condition> {
+    Ok(Some(Object::Port(env.output.clone())))
+}
+
+fn current_error_port(_: Vec<Object>, _: &mut Env) -> Result<Option<Object>, Condition> {
+    Ok(Some(Object::Port(Port::stderr())))
+}
+
+fn read_line(args: Vec<Object>, env: &mut Env) -> Result<Option<Object>, Condition> {
+    let port = try!(optional_port(&args, 0, "read-line", &env.input));
+    match port.read_line() {
+        Ok(Some(line)) => Ok(Some(Object::String(line))),
+        Ok(None) => Ok(Some(Object::Nil)),
+        Err(e) => Err(io_error(e, Object::Port(port))),
+    }
+}
+
+fn read_char(args: Vec<Object>, env: &mut Env) -> Result<Option<Object>, Condition> {
+    let port = try!(optional_port(&args, 0, "read-char", &env.input));
+    match port.read_char() {
+        Ok(Some(c)) => Ok(Some(Object::String(c.to_string()))),
+        Ok(None) => Ok(Some(Object::Nil)),
+        Err(e) => Err(io_error(e, Object::Port(port))),
+    }
+}
+
+fn write_string(args: Vec<Object>, env: &mut Env) -> Result<Option<Object>, Condition> {
+    let s: String = try!(from_arg(args.get(0).cloned(), "write-string", "string"));
+    let port = try!(optional_port(&args, 1, "write-string", &env.output));
+    try!(port.write_str(&s).map_err(|e| io_error(e, Object::Port(port.clone()))));
+    Ok(None)
+}
+
+fn with_output_to_string(args: Vec<Object>, env: &mut Env) -> Result<Option<Object>, Condition> {
+    let thunk = try!(unwrap_functions(args, "with-output-to-string", 1)).remove(0);
+    let port = Port::output_string();
+    let previous = mem::replace(&mut env.output, port.clone());
+    let result = apply_function(thunk, Vec::new(), env);
+    env.output = previous;
+    match try!(result) {
+        Some(Object::Exit(message)) => Ok(Some(Object::Exit(message))),
+        _ => Ok(Some(Object::String(port.contents().unwrap()))),
+    }
+}
+
+fn call_with_output_file(args: Vec<Object>, env: &mut Env) -> Result<Option<Object>, Condition> {
+    let path: String = try!(from_arg(args.get(0).cloned(), "call-with-output-file", "path"));
+    let receiver: Function = try!(from_arg(args.get(1).cloned(), "call-with-output-file", "f"));
+    let port = try!(Port::open_output_file(&path).map_err(|e| open_error(e, path)));
+    let result = apply_function(receiver, vec![Object::Port(port.clone())], env);
+    try!(port.close().map_err(|e| io_error(e, Object::Port(port.clone()))));
+    result
+}
+
+// Reads the text of one form from port: a bracketed list, a string or an
+// atom, with any leading quotes.
+fn read_form_text(port: &Port) -> io::Result<String> {
+    let mut text = String::new();
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    while let Some(c) = try!(port.read_char()) {
+        if in_string {
+            text.push(c);
+            in_string = escaped || c != '"';
+            escaped = !escaped && c == '\\';
+            if !in_string && depth == 0 {
+                break;
+            }
+            continue;
+        }
+        match c {
+            '"' => in_string = true,
+            '(' | '[' => depth += 1,
+            ')' | ']' if depth > 0 => depth -= 1,
+            c if c.is_whitespace() => {
+                if depth == 0 {
+                    if text.chars().all(|c| c == '\'') {
+                        continue;
+                    }
+                    break;
+                }
+            }
+            _ => {}
+        }
+        text.push(c);
+        if depth == 0 && (c == ')' || c == ']') {
+            break;
+        }
+    }
+    Ok(text)
+}
+
+fn read(args: Vec<Object>, env: &mut Env) -> Result<Option<Object>, Condition> {
+    let text = match args.into_iter().next() {
+        Some(Object::String(text)) => text,
+        Some(Object::Port(port)) => try!(read_form_text(&port).map_err(|e| io_error(e, Object::Port(port.clone())))),
+        Some(other) => return Err(Condition::with_irritants("type-error", "read expects a string or port".to_string(), vec![other])),
+        None => {
+            let port = env.input.clone();
+            try!(read_form_text(&port).map_err(|e| io_error(e, Object::Port(port.clone()))))
+        }
+    };
+    match parse(&text) {
+        Ok(exprs) => Ok(Some(exprs.first().map(|expr| expr.to_object()).unwrap_or(Object::Nil))),
+        Err(message) => Err(Condition::with_irritants("syntax-error", message, vec![Object::String(text)])),
+    }
+}
+
+fn eval(args: Vec<Object>, env: &mut Env) -> Result<Option<Object>, Condition> {
+    let expr = Expr::from_object(&args[0]);
+    match args.get(1) {
+        None => eval_form(&expr, env),
+        Some(&Object::Environment(ref environment)) => environment.eval(&expr, env),
+        Some(other) => Err(Condition::with_irritants("type-error", "eval expects an environment".to_string(), vec![other.clone()])),
+    }
+}
+
+fn load(args: Vec<Object>, env: &mut Env) -> Result<Option<Object>, Condition> {
+    let path: String = try!(from_arg(args.into_iter().next(), "load", "path"));
+    let exprs = try!(read_file(Path::new(&path)));
+    let mut result = None;
+    for expr in exprs.iter() {
+        result = try!(eval_form(expr, env));
+        if let Some(Object::Exit(_)) = result {
+            break;
+        }
+    }
+    Ok(result)
+}
+
+fn standard_environment(_: Vec<Object>, _: &mut Env) -> Result<Option<Object>, Condition> {
+    Ok(Some(Object::Environment(Environment::new(Env::new().variables))))
+}
+
+fn require(args: Vec<Object>, env: &mut Env) -> Result<Option<Object>, Condition> {
+    let name: String = try!(from_arg(args.into_iter().next(), "require", "name"));
+    try!(module::require(&name, env));
+    Ok(None)
+}
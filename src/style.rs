@@ -0,0 +1,36 @@
+//! Terminal colours for the repl, off when output is not a terminal, with
+//! `--no-color` or when `NO_COLOR` is set.
+
+use std::env;
+use std::io::{self, IsTerminal};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub const SYMBOL: &'static str = "36";
+pub const NUMBER: &'static str = "33";
+pub const STRING: &'static str = "32";
+pub const SPECIAL_FORM: &'static str = "1;35";
+pub const COMMENT: &'static str = "90";
+pub const MATCHING_BRACKET: &'static str = "1;4";
+pub const ERROR: &'static str = "31";
+
+/// Turns colour on unless `no_color` is set, `NO_COLOR` is set or stdout is
+/// not a terminal.
+pub fn init(no_color: bool) {
+    let no_color_env = env::var_os("NO_COLOR").map(|value| !value.is_empty()).unwrap_or(false);
+    ENABLED.store(!no_color && !no_color_env && io::stdout().is_terminal(), Ordering::SeqCst);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::SeqCst)
+}
+
+/// `text` in the colour given by the SGR codes `style`, if colour is on.
+pub fn paint(style: &str, text: &str) -> String {
+    if enabled() {
+        format!("\x1b[{}m{}\x1b[0m", style, text)
+    } else {
+        text.to_string()
+    }
+}
@@ -0,0 +1,234 @@
+//! A syntax tree that keeps the position of every form and comment, for tools
+//! that point into source or rewrite it.
+//!
+//! Reading never fails: errors are collected alongside the forms that could be
+//! read, so a half-written file still has a tree.
+
+use data::Object;
+use lexer::{Token, TokenKind, lex};
+use parser::{MAX_NESTING, atomize};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum NodeKind {
+    /// `(...)`
+    List(Vec<Node>),
+    /// `[...]`, a conditional case
+    Case(Vec<Node>),
+    /// `'form`, with any comments between the quote and the form
+    Quote(Vec<Node>, Box<Node>),
+    Atom,
+    String,
+    Comment,
+}
+
+/// A form or comment covering the bytes `start..end` of the source.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Node {
+    pub kind: NodeKind,
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxError {
+    pub message: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Node {
+    pub fn text<'a>(&self, source: &'a str) -> &'a str {
+        &source[self.start..self.end]
+    }
+    /// The name, if this is an atom that reads as a symbol.
+    pub fn symbol<'a>(&self, source: &'a str) -> Option<&'a str> {
+        match self.kind {
+            NodeKind::Atom => {
+                match atomize(self.text(source).to_string()) {
+                    Object::Symbol(_) => Some(self.text(source)),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+    /// The nodes inside a list or case, comments included.
+    pub fn children(&self) -> &[Node] {
+        match self.kind {
+            NodeKind::List(ref children) | NodeKind::Case(ref children) => children,
+            _ => &[],
+        }
+    }
+    /// The forms inside a list or case, without comments.
+    pub fn forms(&self) -> Vec<&Node> {
+        self.children().iter().filter(|child| child.kind != NodeKind::Comment).collect()
+    }
+    /// The symbol at the head of a list, such as `define` in `(define x 1)`.
+    pub fn head<'a>(&self, source: &'a str) -> Option<&'a str> {
+        match self.kind {
+            NodeKind::List(_) => self.forms().first().and_then(|head| head.symbol(source)),
+            _ => None,
+        }
+    }
+}
+
+/// Reads every form and comment in `source`.
+pub fn read(source: &str) -> (Vec<Node>, Vec<SyntaxError>) {
+    let tokens = lex(source).into_iter().filter(|token| token.kind != TokenKind::Whitespace).collect::<Vec<_>>();
+    let mut reader = Reader {
+        source: source,
+        tokens: tokens,
+        pos: 0,
+        depth: 0,
+        errors: Vec::new(),
+    };
+    let mut nodes = Vec::new();
+    while reader.pos < reader.tokens.len() {
+        if let Some(node) = reader.node() {
+            nodes.push(node);
+        }
+    }
+    (nodes, reader.errors)
+}
+
+struct Reader<'a> {
+    source: &'a str,
+    tokens: Vec<Token>,
+    pos: usize,
+    // how many lists, cases and quotes the next token is inside
+    depth: usize,
+    errors: Vec<SyntaxError>,
+}
+
+impl<'a> Reader<'a> {
+    fn error(&mut self, message: &str, start: usize, end: usize) {
+        self.errors.push(SyntaxError {
+            message: message.to_string(),
+            start: start,
+            end: end,
+        });
+    }
+
+    // The next node, or `None` if the next token cannot start one. Forms
+    // nested more than `MAX_NESTING` deep are reported and skipped.
+    fn node(&mut self) -> Option<Node> {
+        let token = self.tokens[self.pos];
+        self.pos += 1;
+        let opens = match token.kind {
+            TokenKind::OpenParen | TokenKind::OpenBracket | TokenKind::Quote => true,
+            _ => false,
+        };
+        if opens && self.depth == MAX_NESTING {
+            self.error(&format!("Nested more than {} deep", MAX_NESTING), token.start, token.end);
+            if token.kind != TokenKind::Quote {
+                self.skip_list();
+            }
+            return None;
+        }
+        let kind = match token.kind {
+            TokenKind::OpenParen => return Some(self.list(token, TokenKind::CloseParen, ")")),
+            TokenKind::OpenBracket => return Some(self.list(token, TokenKind::CloseBracket, "]")),
+            TokenKind::CloseParen | TokenKind::CloseBracket => {
+                let message = format!("Unexpected {}", token.text(self.source));
+                self.error(&message, token.start, token.end);
+                return None;
+            }
+            TokenKind::Quote => {
+                let mut comments = Vec::new();
+                while let Some(comment) = self.tokens.get(self.pos).cloned().filter(|t| t.kind == TokenKind::Comment) {
+                    comments.push(Node {
+                        kind: NodeKind::Comment,
+                        start: comment.start,
+                        end: comment.end,
+                    });
+                    self.pos += 1;
+                }
+                self.depth += 1;
+                let quoted = match self.tokens.get(self.pos) {
+                    Some(next) if next.kind != TokenKind::CloseParen && next.kind != TokenKind::CloseBracket => self.node(),
+                    _ => None,
+                };
+                self.depth -= 1;
+                return match quoted {
+                    Some(quoted) => {
+                        Some(Node {
+                            start: token.start,
+                            end: quoted.end,
+                            kind: NodeKind::Quote(comments, Box::new(quoted)),
+                        })
+                    }
+                    None => {
+                        self.error("Nothing to quote", token.start, token.end);
+                        None
+                    }
+                };
+            }
+            TokenKind::String => NodeKind::String,
+            TokenKind::UnterminatedString => {
+                self.error("No end quote", token.start, token.end);
+                NodeKind::String
+            }
+            TokenKind::Comment => NodeKind::Comment,
+            TokenKind::Atom | TokenKind::Whitespace => NodeKind::Atom,
+        };
+        Some(Node {
+            kind: kind,
+            start: token.start,
+            end: token.end,
+        })
+    }
+
+    // The nodes up to the closing token. An unclosed list runs to the end of
+    // the source; a mismatched closing token is reported and skipped.
+    fn list(&mut self, open: Token, closing: TokenKind, closing_text: &str) -> Node {
+        self.depth += 1;
+        let mut children = Vec::new();
+        let end = loop {
+            match self.tokens.get(self.pos).cloned() {
+                Some(token) if token.kind == closing => {
+                    self.pos += 1;
+                    break token.end;
+                }
+                Some(token) if token.kind == TokenKind::CloseParen || token.kind == TokenKind::CloseBracket => {
+                    self.pos += 1;
+                    let message = format!("Expected {} but found {}", closing_text, token.text(self.source));
+                    self.error(&message, token.start, token.end);
+                }
+                Some(_) => {
+                    if let Some(node) = self.node() {
+                        children.push(node);
+                    }
+                }
+                None => {
+                    self.error(&format!("Missing {}", closing_text), open.start, open.end);
+                    break self.source.len();
+                }
+            }
+        };
+        self.depth -= 1;
+        let kind = if closing == TokenKind::CloseParen {
+            NodeKind::List(children)
+        } else {
+            NodeKind::Case(children)
+        };
+        Node {
+            kind: kind,
+            start: open.start,
+            end: end,
+        }
+    }
+
+    // Skips past the close of the list or case just opened, and all in it.
+    fn skip_list(&mut self) {
+        let mut open = 1;
+        while open > 0 {
+            match self.tokens.get(self.pos).map(|token| token.kind) {
+                Some(TokenKind::OpenParen) | Some(TokenKind::OpenBracket) => open += 1,
+                Some(TokenKind::CloseParen) | Some(TokenKind::CloseBracket) => open -= 1,
+                Some(_) => {}
+                None => return,
+            }
+            self.pos += 1;
+        }
+    }
+}
@@ -0,0 +1,119 @@
+//! Subcommands that work on source files rather than running them.
+
+use rlisp::format::format;
+use rlisp::lexer::line_column;
+use rlisp::lint::lint;
+use rlisp::syntax::read;
+use std::fs::File;
+use std::io::{self, Read, Write};
+
+/// How `rlisp fmt` treats the files it formats.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FormatMode {
+    /// Print the formatted source.
+    Print,
+    /// Report files that are not formatted, changing nothing.
+    Check,
+    /// Rewrite files in place.
+    Write,
+}
+
+/// Formats each file, or stdin if there are none. Returns the exit code: 1 if
+/// a file could not be formatted or, when checking, is not formatted.
+pub fn fmt(files: &[&str], mode: FormatMode, width: usize) -> i32 {
+    if files.is_empty() {
+        let mut source = String::new();
+        if let Err(e) = io::stdin().read_to_string(&mut source) {
+            eprintln!("Failed to read stdin: {}", e);
+            return 1;
+        }
+        // there is no file to write back to
+        let mode = if mode == FormatMode::Write { FormatMode::Print } else { mode };
+        return fmt_source("<stdin>", &source, mode, width).map(|_| 0).unwrap_or(1);
+    }
+    let mut code = 0;
+    for &file in files {
+        let mut source = String::new();
+        if let Err(e) = File::open(file).and_then(|mut f| f.read_to_string(&mut source)) {
+            eprintln!("Failed to read {}: {}", file, e);
+            code = 1;
+            continue;
+        }
+        match fmt_source(file, &source, mode, width) {
+            Some(Some(formatted)) => {
+                if let Err(e) = File::create(file).and_then(|mut f| f.write_all(formatted.as_bytes())) {
+                    eprintln!("Failed to write {}: {}", file, e);
+                    code = 1;
+                }
+            }
+            Some(None) => {}
+            None => code = 1,
+        }
+    }
+    code
+}
+
+/// Lints each file, or stdin if there are none, printing the syntax errors
+/// and warnings found. Returns the exit code: 1 if anything was found.
+pub fn lint_files(files: &[&str]) -> i32 {
+    let mut sources = Vec::new();
+    if files.is_empty() {
+        let mut source = String::new();
+        if let Err(e) = io::stdin().read_to_string(&mut source) {
+            eprintln!("Failed to read stdin: {}", e);
+            return 1;
+        }
+        sources.push(("<stdin>", source));
+    }
+    let mut code = 0;
+    for &file in files {
+        let mut source = String::new();
+        match File::open(file).and_then(|mut f| f.read_to_string(&mut source)) {
+            Ok(_) => sources.push((file, source)),
+            Err(e) => {
+                eprintln!("Failed to read {}: {}", file, e);
+                code = 1;
+            }
+        }
+    }
+    for (name, source) in sources {
+        let (nodes, errors) = read(&source);
+        for error in errors.iter() {
+            let (line, column) = line_column(&source, error.start);
+            println!("{}:{}:{}: error: {}", name, line, column, error.message);
+            code = 1;
+        }
+        for warning in lint(&nodes, &source) {
+            let (line, column) = line_column(&source, warning.start);
+            println!("{}:{}:{}: warning: {} [{}]", name, line, column, warning.message, warning.kind);
+            code = 1;
+        }
+    }
+    code
+}
+
+// Formats one source. `None` on failure, or when checking and the source is
+// not formatted; otherwise the text to write back, if there is any.
+fn fmt_source(name: &str, source: &str, mode: FormatMode, width: usize) -> Option<Option<String>> {
+    let formatted = match format(source, width) {
+        Ok(formatted) => formatted,
+        Err(error) => {
+            let (line, column) = line_column(source, error.start);
+            eprintln!("{}:{}:{}: {}", name, line, column, error.message);
+            return None;
+        }
+    };
+    match mode {
+        FormatMode::Print => {
+            print!("{}", formatted);
+            Some(None)
+        }
+        FormatMode::Check if formatted != source => {
+            eprintln!("{} is not formatted", name);
+            None
+        }
+        FormatMode::Check => Some(None),
+        FormatMode::Write if formatted != source => Some(Some(formatted)),
+        FormatMode::Write => Some(None),
+    }
+}
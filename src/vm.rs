@@ -0,0 +1,196 @@
+use data::*;
+use module::Namespace;
+use compiler::{Chunk, Op, compile_expr};
+use eval::{Eval, apply_function};
+use std::collections::HashMap;
+use std::mem;
+use std::sync::Arc;
+
+struct Frame {
+    chunk: Arc<Chunk>,
+    ip: usize,
+    locals: Vec<Object>,
+    stack_base: usize,
+    is_call: bool, // counted against the env's call depth
+    caller_namespace: Option<Namespace>, // restored when the frame is left
+}
+
+struct Vm {
+    stack: Vec<Option<Object>>,
+    frames: Vec<Frame>,
+}
+
+pub fn eval(expr: &Expr, env: &mut Env) -> Result<Option<Object>, Condition> {
+    let mut vm = Vm {
+        stack: Vec::new(),
+        frames: vec![Frame {
+            chunk: Arc::new(compile_expr(expr)),
+            ip: 0,
+            locals: Vec::new(),
+            stack_base: 0,
+            is_call: false,
+            caller_namespace: env.namespace.clone(),
+        }],
+    };
+    vm.run(env)
+}
+
+pub fn apply(function: Function, args: Vec<Object>, env: &mut Env) -> Result<Option<Object>, Condition> {
+    let mut vm = Vm {
+        stack: Vec::new(),
+        frames: Vec::new(),
+    };
+    try!(vm.call(function, args, env));
+    if vm.frames.is_empty() {
+        Ok(vm.stack.pop().unwrap())
+    } else {
+        vm.run(env)
+    }
+}
+
+impl Vm {
+    fn call(&mut self, function: Function, args: Vec<Object>, env: &mut Env) -> Result<(), Condition> {
+        match *function.procedure {
+            LispFn::UserDef(ref vars, ref body, ref code) => {
+                if args.len() != vars.len() {
+                    return Err(Condition::new("arity-error", format!("Function {:?} run with {} args; should be run with {} args", function, args.len(), vars.len())));
+                }
+                try!(env.enter_call());
+                self.frames.push(Frame {
+                    chunk: code.get_or_compile(vars, body),
+                    ip: 0,
+                    locals: args,
+                    stack_base: self.stack.len(),
+                    is_call: true,
+                    caller_namespace: mem::replace(&mut env.namespace, function.namespace.clone()),
+                });
+            }
+            _ => {
+                let result = try!(apply_function(function.clone(), args, env));
+                self.stack.push(result);
+            }
+        }
+        Ok(())
+    }
+
+    fn run(&mut self, env: &mut Env) -> Result<Option<Object>, Condition> {
+        let mut condition = match self.run_frames(env) {
+            Err(condition) => condition,
+            result => return result,
+        };
+        for _ in self.frames.iter().filter(|frame| frame.is_call) {
+            env.exit_call();
+        }
+        if let Some(first) = self.frames.first() {
+            env.namespace = first.caller_namespace.clone();
+        }
+        // each frame was entered by the call op its caller last ran; the
+        // first frame's call, if it had one, was outside this vm
+        for i in (1..self.frames.len()).rev() {
+            let caller = &self.frames[i - 1];
+            if let Op::Call(_, ref call) = caller.chunk.code[caller.ip - 1] {
+                condition = condition.called_from(call);
+            }
+        }
+        Err(condition)
+    }
+
+    fn run_frames(&mut self, env: &mut Env) -> Result<Option<Object>, Condition> {
+        loop {
+            try!(env.step());
+            let (chunk, ip) = {
+                let frame = self.frames.last_mut().unwrap();
+                frame.ip += 1;
+                (frame.chunk.clone(), frame.ip - 1)
+            };
+            if ip == chunk.code.len() {
+                let frame = self.frames.pop().unwrap();
+                if frame.is_call {
+                    env.exit_call();
+                }
+                env.namespace = frame.caller_namespace;
+                let result = self.stack.pop().unwrap();
+                self.stack.truncate(frame.stack_base);
+                if self.frames.is_empty() {
+                    return Ok(result);
+                }
+                self.stack.push(result);
+                continue;
+            }
+            match chunk.code[ip] {
+                Op::Const(ref object) => self.stack.push(Some(object.clone())),
+                Op::Lambda(ref function) => {
+                    let function = function.clone().in_namespace(env.namespace.clone());
+                    self.stack.push(Some(Object::Function(function)));
+                }
+                Op::LoadLocal(slot) => {
+                    let value = self.frames.last().unwrap().locals[slot].clone();
+                    self.stack.push(Some(value));
+                }
+                Op::LoadGlobal(ref name) => {
+                    match env.lookup(name) {
+                        Some(value) => self.stack.push(Some(value)),
+                        None => return Err(Condition::new("unbound-variable", format!("No such variable {}", name))),
+                    }
+                }
+                Op::LoadFunction(ref name) => {
+                    match env.get_function(name) {
+                        Some(function) => self.stack.push(Some(Object::Function(function))),
+                        None => return Err(Condition::new("unbound-variable", format!("No such function {:?}", name))),
+                    }
+                }
+                Op::Call(argc, ref call) => {
+                    let args_start = self.stack.len() - argc;
+                    let args = self.stack.split_off(args_start);
+                    let head = self.stack.pop().unwrap();
+                    let exit = args.iter().position(|arg| {
+                        if let Some(Object::Exit(_)) = *arg {
+                            true
+                        } else {
+                            false
+                        }
+                    });
+                    if let Some(exit) = exit {
+                        self.stack.push(args[exit].clone());
+                        continue;
+                    }
+                    let args = args.into_iter().filter_map(|arg| arg).collect::<Vec<_>>();
+                    match head {
+                        Some(Object::Function(function)) => {
+                            try!(self.call(function, args, env).map_err(|condition| condition.called_from(call)))
+                        }
+                        None => return Err(Condition::new("type-error", "Function position has no value".to_string())),
+                        other => return Err(Condition::new("type-error", format!("Invalid function name {:?}", other))),
+                    }
+                }
+                Op::Pop => {
+                    self.stack.pop();
+                }
+                Op::JumpUnless(target, ref case) => {
+                    match self.stack.pop().unwrap() {
+                        Some(Object::Boolean(Boolean::True)) => {}
+                        Some(Object::Boolean(Boolean::False)) => self.frames.last_mut().unwrap().ip = target,
+                        _ => return Err(Condition::new("type-error", format!("Case {:?} does not return a boolean", case))),
+                    }
+                }
+                Op::Jump(target) => self.frames.last_mut().unwrap().ip = target,
+                Op::Interpret(ref expr) => {
+                    let result = {
+                        let frame = self.frames.last().unwrap();
+                        if frame.locals.is_empty() {
+                            try!(expr.eval(env))
+                        } else {
+                            let mut var_mappings = HashMap::new();
+                            for (var, value) in chunk.params.iter().zip(frame.locals.iter()) {
+                                var_mappings.insert(var, value);
+                            }
+                            try!(expr.replace_all(&var_mappings).eval(env))
+                        }
+                    };
+                    self.stack.push(result);
+                }
+                Op::Fail(ref condition) => return Err(condition.clone()),
+            }
+        }
+    }
+}
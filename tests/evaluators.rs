@@ -0,0 +1,112 @@
+//! The bytecode vm and the tree-walker should give the same results, errors
+//! and backtraces for the same forms.
+
+extern crate rlisp;
+
+use rlisp::{Env, Error, Evaluator, Interpreter};
+
+// The result of evaluating `input`, or its error with the functions in its
+// backtrace.
+fn run(evaluator: Evaluator, input: &str) -> String {
+    let mut env = Env::new();
+    env.evaluator = evaluator;
+    match Interpreter::with_env(env).eval_str(input) {
+        Ok(result) => format!("{:?}", result),
+        Err(Error::Eval(condition)) => {
+            let calls = condition.backtrace.iter().map(|site| site.function.clone()).collect::<Vec<_>>();
+            format!("{} {:?}", condition, calls)
+        }
+        Err(e) => panic!("Failed to parse {}: {}", input, e),
+    }
+}
+
+fn assert_same(input: &str, expected: &str) {
+    let bytecode = run(Evaluator::Bytecode, input);
+    let tree_walk = run(Evaluator::TreeWalk, input);
+    assert_eq!(bytecode, tree_walk, "evaluators disagree on {}", input);
+    assert!(bytecode.contains(expected), "{} gave {}, expected {}", input, bytecode, expected);
+}
+
+#[test]
+fn cond() {
+    assert_same("(define (name n) (cond [(= n 0) \"zero\"] [(= n 1) \"one\"] [else \"many\"])) (list (name 0) (name 1) (name 7))",
+                "List([String(\"zero\"), String(\"one\"), String(\"many\")])");
+    assert_same("(cond [(= 1 2) 1] [(= 1 3) 2])", "syntax-error: Invalid final arg for cond");
+}
+
+#[test]
+fn closures() {
+    assert_same("(define (adder n) (lambda (x) (+ x n))) (define add2 (adder 2)) (add2 40)",
+                "Number(Int(42))");
+    assert_same("(define (adder n) (lambda (x) (+ x n)))
+                 (define (compose f g) (lambda (x) (f (g x))))
+                 ((compose (adder 1) (adder 10)) 0)",
+                "Number(Int(11))");
+    assert_same("(define (twice f x) (f (f x))) (twice (lambda (x) (* x 3)) 2)", "Number(Int(18))");
+}
+
+#[test]
+fn recursion() {
+    assert_same("(define (fact n) (cond [(= n 0) 1] [else (* n (fact (- n 1)))])) (fact 10)",
+                "Number(Int(3628800))");
+}
+
+#[test]
+fn guard() {
+    assert_same("(guard (e [(error-object? e) (error-object-message e)]) (error \"bad\" 1))",
+                "String(\"bad\")");
+    assert_same("(guard (e [(= e \"raised\") e]) (+ 1 (raise \"raised\")))", "String(\"raised\")");
+    // no clause matches, so the condition goes on unwinding
+    assert_same("(guard (e [(= e \"raised\") e]) (raise 5))", "5");
+}
+
+#[test]
+fn call_cc() {
+    assert_same("(+ 1 (call/cc (lambda (k) (+ 10 (k 2)))))", "Number(Int(3))");
+    assert_same("(define (first-zero a b c)
+                   (call/cc (lambda (return)
+                     (cond [(= a 0) (return 1)] [else 0])
+                     (cond [(= b 0) (return 2)] [else 0])
+                     (cond [(= c 0) (return 3)] [else 0])
+                     0)))
+                 (first-zero 1 0 0)",
+                "Number(Int(2))");
+}
+
+#[test]
+fn set() {
+    assert_same("(define n 1) (set! n (+ n 1)) n", "Number(Int(2))");
+    assert_same("(define (counter) (define count 0) (lambda () (set! count (+ count 1)) count))
+                 (define next (counter)) (next) (next)",
+                "Number(Int(2))");
+    assert_same("(set! unbound 1)", "unbound");
+}
+
+#[test]
+fn backtraces() {
+    assert_same("(define (inner x) (/ x \"a\")) (define (outer x) (inner x)) (outer 1)",
+                "[\"/\", \"inner\", \"outer\"]");
+    assert_same("(define (count-down n) (cond [(= n 0) (error \"done\")] [else (count-down (- n 1))])) (count-down 3)",
+                "[\"error\", \"count-down\", \"count-down\", \"count-down\", \"count-down\"]");
+    assert_same("((lambda (x) (undefined-function x)) 1)", "");
+}
+
+// Where each call in the backtrace of `input`'s error was read from.
+fn positions(evaluator: Evaluator, input: &str) -> Vec<Option<String>> {
+    let mut env = Env::new();
+    env.evaluator = evaluator;
+    match Interpreter::with_env(env).eval_str(input) {
+        Err(Error::Eval(condition)) => condition.backtrace.iter().map(|site| site.span.as_ref().map(|span| span.position())).collect(),
+        other => panic!("expected an error from {}, got {:?}", input, other),
+    }
+}
+
+#[test]
+fn backtrace_positions() {
+    let input = "(define (f x) (* x x))\n(define (g) (f 1) (f \"s\"))\n(g)";
+    let expected = vec![Some("<input>:1:15".to_string()), Some("<input>:2:19".to_string()), Some("<input>:3:1".to_string())];
+    assert_eq!(positions(Evaluator::Bytecode, input), expected);
+    assert_eq!(positions(Evaluator::TreeWalk, input), expected);
+    // forms made by eval were never read
+    assert_eq!(positions(Evaluator::Bytecode, "(eval (list '* 1 \"s\"))"), vec![None, Some("<input>:1:1".to_string())]);
+}
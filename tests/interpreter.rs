@@ -0,0 +1,138 @@
+//! The embedding API.
+
+extern crate rlisp;
+
+use rlisp::{Env, Error, Evaluator, Interpreter, Number, Object, Port};
+use rlisp::data::Expr;
+use rlisp::debug::{DEBUG_MAX_DEPTH, Step};
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::process;
+use std::thread;
+use std::time::Duration;
+
+#[test]
+fn get_as() {
+    let mut interpreter = Interpreter::new();
+    interpreter.eval_str("(define n 3) (define name \"rlisp\")").unwrap();
+    assert_eq!(interpreter.get_as::<i64>("n").unwrap(), 3);
+    assert_eq!(interpreter.get_as::<f64>("n").unwrap(), 3.0);
+    assert_eq!(interpreter.get_as::<String>("name").unwrap(), "rlisp");
+    match interpreter.get_as::<String>("n") {
+        Err(Error::Eval(condition)) => assert_eq!(condition.kind, "type-error"),
+        other => panic!("expected a type error, got {:?}", other),
+    }
+    match interpreter.get_as::<i64>("missing") {
+        Err(Error::Eval(condition)) => assert_eq!(condition.kind, "unbound-variable"),
+        other => panic!("expected an unbound variable, got {:?}", other),
+    }
+}
+
+#[test]
+fn call_as() {
+    let mut interpreter = Interpreter::new();
+    let output = Port::output_string();
+    interpreter.env_mut().output = output.clone();
+    interpreter.eval_str("(define (square x) (* x x)) (define (pair x) (list x x)) (define (nothing) (print \"\"))").unwrap();
+    let three = Object::Number(Number::Int(3));
+    assert_eq!(interpreter.call_as::<i64>("square", vec![three.clone()]).unwrap(), 9);
+    assert_eq!(interpreter.call_as::<Vec<i64>>("pair", vec![three.clone()]).unwrap(), vec![3, 3]);
+    assert!(interpreter.call_as::<Vec<String>>("pair", vec![three]).is_err());
+    assert_eq!(interpreter.call_as::<Option<i64>>("nothing", vec![]).unwrap(), None);
+    assert!(interpreter.call_as::<i64>("nothing", vec![]).is_err());
+    assert_eq!(output.contents(), Some("String(\"\")\nString(\"\")\n".to_string()));
+    let _: HashMap<String, i64> = interpreter.call_as("hash-table", vec![]).unwrap();
+}
+
+#[test]
+fn eval_file() {
+    let path = env::temp_dir().join(format!("rlisp-eval-file-{}.lisp", process::id()));
+    fs::write(&path, "(define x 1)\n(+ x 1)").unwrap();
+    let mut interpreter = Interpreter::new();
+    assert_eq!(interpreter.eval_file(path.to_str().unwrap()).unwrap(), Some(Object::Number(Number::Int(2))));
+    fs::write(&path, "(define x 1)\n(+ x").unwrap();
+    match interpreter.eval_file(path.to_str().unwrap()) {
+        Err(Error::Eval(condition)) => {
+            assert_eq!(condition.kind, "syntax-error");
+            assert!(condition.message.contains("line 2"), "{}", condition.message);
+            assert_eq!(condition.irritants, vec![Object::String(path.display().to_string())]);
+        }
+        other => panic!("expected a syntax error, got {:?}", other),
+    }
+    fs::remove_file(&path).unwrap();
+    match interpreter.eval_file(path.to_str().unwrap()) {
+        Err(Error::Eval(condition)) => assert_eq!(condition.kind, "io-error"),
+        other => panic!("expected an io error, got {:?}", other),
+    }
+}
+
+#[test]
+fn default_depth() {
+    // the stack the docs ask for at the default depth
+    let run = thread::Builder::new().stack_size(32 * 1024 * 1024).spawn(|| {
+        for &evaluator in [Evaluator::Bytecode, Evaluator::TreeWalk].iter() {
+            let mut env = Env::new();
+            env.evaluator = evaluator;
+            let mut interpreter = Interpreter::with_env(env);
+            interpreter.eval_str("(define (down n) (cond [(= n 0) 0] [else (+ 1 (down (- n 1)))]))").unwrap();
+            assert_eq!(interpreter.eval_str("(down 990)").unwrap(), interpreter.eval_str("990").unwrap());
+            match interpreter.eval_str("(down 1000)") {
+                Err(Error::Eval(condition)) => assert_eq!(condition.message, "Evaluation exceeded a call depth of 1000"),
+                other => panic!("expected the depth limit, got {:?}", other),
+            }
+        }
+    });
+    run.unwrap().join().unwrap();
+}
+
+#[test]
+fn nested_forms_are_limited() {
+    // stack enough to clone the form, but not to evaluate it without a limit
+    let run = thread::Builder::new().stack_size(16 * 1024 * 1024).spawn(|| {
+        let mut form = Expr::Expr(Object::Number(Number::Int(0)));
+        for _ in 0..5000 {
+            form = Expr::form(vec![Expr::Expr(Object::Symbol("+".to_string())), Expr::Expr(Object::Number(Number::Int(1))), form]);
+        }
+        for &evaluator in [Evaluator::Bytecode, Evaluator::TreeWalk].iter() {
+            let mut env = Env::new();
+            env.evaluator = evaluator;
+            match Interpreter::with_env(env).eval_expr(&form) {
+                Err(Error::Eval(condition)) => assert_eq!(condition.message, "Evaluation nested forms more than 512 deep"),
+                other => panic!("expected the nesting limit, got {:?}", other),
+            }
+        }
+    });
+    run.unwrap().join().unwrap();
+}
+
+#[test]
+fn debugging_depth() {
+    // enough stack for the tree-walker to reach DEBUG_MAX_DEPTH
+    let run = thread::Builder::new().stack_size(256 * 1024 * 1024).spawn(|| {
+        let mut interpreter = Interpreter::new();
+        interpreter.env_mut().limits.max_depth = Some(10000);
+        interpreter.attach_debugger(|_, _| Step::Continue);
+        interpreter.eval_str("(define (down n) (cond [(= n 0) 0] [else (+ 1 (down (- n 1)))]))").unwrap();
+        assert!(interpreter.eval_str(&format!("(down {})", DEBUG_MAX_DEPTH / 2)).is_ok());
+        match interpreter.eval_str(&format!("(down {})", DEBUG_MAX_DEPTH)) {
+            Err(Error::Eval(condition)) => assert_eq!(condition.kind, "resource-exhausted"),
+            other => panic!("expected the debugging depth limit, got {:?}", other),
+        }
+    });
+    run.unwrap().join().unwrap();
+}
+
+#[test]
+fn paused_time_is_not_timed() {
+    let mut interpreter = Interpreter::new();
+    interpreter.env_mut().limits.timeout = Some(Duration::from_millis(500));
+    interpreter.attach_debugger(|_, _| {
+        thread::sleep(Duration::from_millis(600));
+        Step::Continue
+    });
+    interpreter.eval_str("(define (spin n) (cond [(= n 0) 0] [else (spin (- n 1)) (spin (- n 1))]))
+                          (define (pauses) (break) (spin 10) (break) (spin 10))")
+        .unwrap();
+    assert_eq!(interpreter.eval_str("(pauses)").unwrap(), Some(Object::Number(Number::Int(0))));
+}
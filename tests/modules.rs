@@ -0,0 +1,92 @@
+//! Modules keep their unexported definitions to themselves.
+
+extern crate rlisp;
+
+use rlisp::{Env, Evaluator, Interpreter, Number, Object};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+const GEO: &'static str = "(module geo (export area counter bump scaler))
+(define (square x) (* x x))
+(define (area r) (* 3 (square r)))
+(define counter 0)
+(define hidden 0)
+(define (bump) (set! counter (+ counter 1)) (set! hidden (+ hidden 1)) hidden)
+(define (scaler k) (lambda (x) (* k (square x))))";
+
+fn library(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("rlisp-modules-{}-{}", name, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("geo.lisp"), GEO).unwrap();
+    dir
+}
+
+fn interpreter(evaluator: Evaluator, dir: PathBuf) -> Interpreter {
+    let mut env = Env::new();
+    env.evaluator = evaluator;
+    env.library_path = vec![dir];
+    let mut interpreter = Interpreter::with_env(env);
+    interpreter.eval_str("(require \"geo\")").unwrap();
+    interpreter
+}
+
+#[test]
+fn private_names() {
+    for &evaluator in [Evaluator::Bytecode, Evaluator::TreeWalk].iter() {
+        let mut interpreter = interpreter(evaluator, library("private"));
+        match interpreter.get("geo/area") {
+            Some(Object::Function(_)) => {}
+            other => panic!("expected geo/area to be a function, got {:?}", other),
+        }
+        for name in ["square", "hidden", "area", "geo/square", "geo/hidden"].iter() {
+            assert!(interpreter.get(name).is_none(), "{} is bound", name);
+        }
+        // the module's own square is used even with another one at top level
+        interpreter.eval_str("(define (square x) 0)").unwrap();
+        assert_eq!(interpreter.eval_str("(geo/area 2)").unwrap(), interpreter.eval_str("12").unwrap());
+        assert_eq!(interpreter.eval_str("((geo/scaler 2) 3)").unwrap(), interpreter.eval_str("18").unwrap());
+        assert!(interpreter.eval_str("(geo/square 2)").is_err());
+        assert!(interpreter.env().variables.keys().all(|name| !name.contains("hidden") && !name.contains(' ')));
+    }
+}
+
+#[test]
+fn set_in_module() {
+    for &evaluator in [Evaluator::Bytecode, Evaluator::TreeWalk].iter() {
+        let mut interpreter = interpreter(evaluator, library("set"));
+        assert_eq!(interpreter.call_as::<i64>("geo/bump", vec![]).unwrap(), 1);
+        assert_eq!(interpreter.call_as::<i64>("geo/bump", vec![]).unwrap(), 2);
+        // exported variables are the same binding inside and out
+        assert_eq!(interpreter.get_as::<i64>("geo/counter").unwrap(), 2);
+        interpreter.eval_str("(set! geo/counter 10) (geo/bump)").unwrap();
+        assert_eq!(interpreter.get_as::<i64>("geo/counter").unwrap(), 11);
+    }
+}
+
+#[test]
+fn nested_requires() {
+    for &evaluator in [Evaluator::Bytecode, Evaluator::TreeWalk].iter() {
+        let dir = library("nested");
+        fs::write(dir.join("quad.lisp"),
+                  "(module quad (export quad)) (require \"geo\") (define (quad r) (* 4 (geo/area r)))")
+            .unwrap();
+        fs::write(dir.join("both.lisp"),
+                  "(module both (export double geo/area)) (require \"geo\") (define (double r) (* 2 (geo/area r)))")
+            .unwrap();
+        let mut env = Env::new();
+        env.evaluator = evaluator;
+        env.library_path = vec![dir];
+        let mut interpreter = Interpreter::with_env(env);
+        // what quad requires stays in its namespace
+        interpreter.eval_str("(require \"quad\")").unwrap();
+        assert_eq!(interpreter.call_as::<i64>("quad/quad", vec![Object::Number(Number::Int(1))]).unwrap(), 12);
+        assert!(interpreter.get("geo/area").is_none());
+        assert!(interpreter.get("quad/geo/area").is_none());
+        // unless it is exported, when it is bound as it is named
+        interpreter.eval_str("(require \"both\")").unwrap();
+        assert_eq!(interpreter.eval_str("(both/double 1)").unwrap(), interpreter.eval_str("6").unwrap());
+        assert_eq!(interpreter.eval_str("(geo/area 1)").unwrap(), interpreter.eval_str("3").unwrap());
+        assert!(interpreter.get("geo/counter").is_none());
+    }
+}
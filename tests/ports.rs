@@ -0,0 +1,99 @@
+//! Ports and the builtins that read and write them.
+
+extern crate rlisp;
+
+use rlisp::{Error, Interpreter, Object, Port};
+use std::env;
+use std::fs;
+
+fn string(s: &str) -> Object {
+    Object::String(s.to_string())
+}
+
+// The value of the last form in `input`, which must have one.
+fn eval(interpreter: &mut Interpreter, input: &str) -> Object {
+    match interpreter.eval_str(input) {
+        Ok(Some(value)) => value,
+        other => panic!("{} gave {:?}", input, other),
+    }
+}
+
+fn error_kind(interpreter: &mut Interpreter, input: &str) -> String {
+    match interpreter.eval_str(input) {
+        Err(Error::Eval(condition)) => condition.kind,
+        other => panic!("expected {} to fail, got {:?}", input, other),
+    }
+}
+
+#[test]
+fn read_lines_and_chars() {
+    let mut interpreter = Interpreter::new();
+    interpreter.eval_str("(define p (open-input-string \"ab\\ncd\\r\\n\u{e9}x\"))").unwrap();
+    assert_eq!(eval(&mut interpreter, "(read-line p)"), string("ab"));
+    assert_eq!(eval(&mut interpreter, "(read-line p)"), string("cd"));
+    assert_eq!(eval(&mut interpreter, "(read-char p)"), string("\u{e9}"));
+    assert_eq!(eval(&mut interpreter, "(read-line p)"), string("x"));
+    // nil at the end of input, however often it is asked
+    assert_eq!(eval(&mut interpreter, "(read-line p)"), Object::Nil);
+    assert_eq!(eval(&mut interpreter, "(read-char p)"), Object::Nil);
+    assert_eq!(eval(&mut interpreter, "(read-line p)"), Object::Nil);
+}
+
+#[test]
+fn read_forms() {
+    let mut interpreter = Interpreter::new();
+    interpreter.eval_str("(define p (open-input-string \"(a [b c]) 'd \\\"e \\\\\\\" f\\\" 42\"))").unwrap();
+    assert_eq!(eval(&mut interpreter, "(read p)"), eval(&mut interpreter, "'(a [b c])"));
+    assert_eq!(eval(&mut interpreter, "(read p)"), eval(&mut interpreter, "''d"));
+    assert_eq!(eval(&mut interpreter, "(read p)"), string("e \" f"));
+    assert_eq!(eval(&mut interpreter, "(read p)"), eval(&mut interpreter, "42"));
+    assert_eq!(eval(&mut interpreter, "(read p)"), Object::Nil);
+    assert_eq!(eval(&mut interpreter, "(read \"(x y)\")"), eval(&mut interpreter, "'(x y)"));
+    assert_eq!(error_kind(&mut interpreter, "(read (open-input-string \"(unclosed\"))"), "syntax-error");
+}
+
+#[test]
+fn output_to_string() {
+    let mut interpreter = Interpreter::new();
+    let output = Port::output_string();
+    interpreter.env_mut().output = output.clone();
+    assert_eq!(eval(&mut interpreter, "(with-output-to-string (lambda () (write-string \"a\") (write-string \"b\")))"),
+               string("ab"));
+    // output goes back where it was afterwards, even after an error
+    assert_eq!(error_kind(&mut interpreter, "(with-output-to-string (lambda () (write-string \"a\") (error \"bad\")))"),
+               "error");
+    interpreter.eval_str("(write-string \"z\")").unwrap();
+    assert_eq!(output.contents(), Some("z".to_string()));
+    assert_eq!(eval(&mut interpreter, "(with-output-to-string (lambda () (write-string \"c\" (current-output-port))))"),
+               string("c"));
+}
+
+#[test]
+fn output_files() {
+    let path = env::temp_dir().join(format!("rlisp-ports-{}.txt", std::process::id()));
+    let path = format!("{:?}", path.to_str().unwrap());
+    let mut interpreter = Interpreter::new();
+    interpreter.eval_str(&format!("(define saved nil)
+                                   (call-with-output-file {} (lambda (port) (set! saved port) (write-string \"one\\ntwo\" port)))",
+                                  path))
+        .unwrap();
+    interpreter.eval_str(&format!("(define p (open-input-file {}))", path)).unwrap();
+    assert_eq!(eval(&mut interpreter, "(read-line p)"), string("one"));
+    assert_eq!(eval(&mut interpreter, "(read-line p)"), string("two"));
+    // the port is closed once the call returns
+    assert_eq!(error_kind(&mut interpreter, "(write-string \"three\" saved)"), "io-error");
+    fs::remove_file(path.trim_matches('"')).unwrap();
+    assert_eq!(error_kind(&mut interpreter, &format!("(open-input-file {})", path)), "io-error");
+}
+
+#[test]
+fn closed_ports() {
+    let mut interpreter = Interpreter::new();
+    interpreter.eval_str("(define p (open-input-string \"abc\")) (close-port p)").unwrap();
+    assert_eq!(error_kind(&mut interpreter, "(read-char p)"), "io-error");
+    assert_eq!(error_kind(&mut interpreter, "(read-line p)"), "io-error");
+    assert_eq!(error_kind(&mut interpreter, "(read p)"), "io-error");
+    // closing again is harmless
+    assert!(interpreter.eval_str("(close-port p)").is_ok());
+    assert_eq!(error_kind(&mut interpreter, "(read-line 5)"), "type-error");
+}
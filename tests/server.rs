@@ -0,0 +1,156 @@
+//! The repl server, run as `rlisp --server` on a port of its own.
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::process::{Child, Command, Stdio};
+
+struct Server {
+    process: Child,
+    address: String,
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+    }
+}
+
+fn start() -> Server {
+    let mut process = Command::new(env!("CARGO_BIN_EXE_rlisp"))
+        .args(&["--server", "127.0.0.1:0"])
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to start the server");
+    let mut line = String::new();
+    BufReader::new(process.stdout.take().unwrap()).read_line(&mut line).unwrap();
+    let address = line.trim().trim_start_matches("Listening on ").to_string();
+    Server {
+        process: process,
+        address: address,
+    }
+}
+
+struct Client {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl Client {
+    fn connect(server: &Server) -> Client {
+        let stream = TcpStream::connect(&server.address).unwrap();
+        Client {
+            reader: BufReader::new(stream.try_clone().unwrap()),
+            writer: stream,
+        }
+    }
+    // The replies to `request`, up to and including its done reply.
+    fn request(&mut self, request: &str) -> Vec<String> {
+        writeln!(self.writer, "{}", request).unwrap();
+        let mut replies = Vec::new();
+        loop {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line).unwrap() == 0 {
+                return replies;
+            }
+            let done = line.contains("\"status\":\"done\"");
+            replies.push(line.trim().to_string());
+            if done {
+                return replies;
+            }
+        }
+    }
+}
+
+// The value of the string field `name` in the JSON object `reply`.
+fn string_field<'a>(reply: &'a str, name: &str) -> &'a str {
+    let start = reply.find(&format!("\"{}\":\"", name)).unwrap() + name.len() + 4;
+    &reply[start..start + reply[start..].find('"').unwrap()]
+}
+
+#[test]
+fn eval() {
+    let server = start();
+    let mut client = Client::connect(&server);
+    let replies = client.request("{\"op\": \"eval\", \"code\": \"(print 1) (+ 1 2)\", \"id\": 7}");
+    assert_eq!(replies.len(), 3, "{:?}", replies);
+    assert!(replies[0].contains("\"out\":\"Number(Int(1))\\n\""), "{:?}", replies);
+    assert!(replies[1].contains("\"value\":\"Number(Int(3))\""), "{:?}", replies);
+    assert!(replies[2].contains("\"status\":\"done\""), "{:?}", replies);
+    assert!(replies.iter().all(|reply| reply.contains("\"id\":7")));
+    let session = string_field(&replies[0], "session").to_string();
+    assert_eq!(session.len(), 32);
+
+    // definitions stay in the session, and evaluation stops at an error
+    client.request("{\"op\": \"eval\", \"code\": \"(define n 41)\"}");
+    let replies = client.request("{\"op\": \"eval\", \"code\": \"(+ n 1) (car n) (+ n 2)\"}");
+    assert_eq!(replies.len(), 3, "{:?}", replies);
+    assert!(replies[0].contains("\"value\":\"Number(Int(42))\""), "{:?}", replies);
+    assert!(replies[1].contains("\"kind\":\"unbound-variable\""), "{:?}", replies);
+
+    let replies = client.request("{\"op\": \"eval\", \"code\": \"(+ 1\"}");
+    assert!(replies[0].contains("\"kind\":\"syntax-error\""), "{:?}", replies);
+    let replies = client.request("[1, 2]");
+    assert!(replies[0].contains("\"kind\":\"protocol-error\""), "{:?}", replies);
+}
+
+#[test]
+fn sessions() {
+    let server = start();
+    let mut first = Client::connect(&server);
+    let replies = first.request("{\"op\": \"clone\"}");
+    let cloned = string_field(&replies[0], "new-session").to_string();
+    assert_eq!(cloned.len(), 32);
+    first.request(&format!("{{\"op\": \"eval\", \"code\": \"(define shared 1)\", \"session\": \"{}\"}}", cloned));
+
+    // another connection can use the session by name but not close it
+    let mut second = Client::connect(&server);
+    let replies = second.request(&format!("{{\"op\": \"eval\", \"code\": \"shared\", \"session\": \"{}\"}}", cloned));
+    assert!(replies[0].contains("\"value\":\"Number(Int(1))\""), "{:?}", replies);
+    let replies = second.request(&format!("{{\"op\": \"close\", \"session\": \"{}\"}}", cloned));
+    assert!(replies[0].contains("belongs to another connection"), "{:?}", replies);
+
+    // the sessions a connection made go with it
+    drop(first);
+    let mut closed = false;
+    for _ in 0..100 {
+        let replies = second.request(&format!("{{\"op\": \"eval\", \"code\": \"shared\", \"session\": \"{}\"}}", cloned));
+        if replies[0].contains("No session") {
+            closed = true;
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(20));
+    }
+    assert!(closed, "the session outlived its connection");
+}
+
+#[test]
+fn long_requests() {
+    let server = start();
+    let mut client = Client::connect(&server);
+    let replies = client.request(&format!("{{\"op\": \"eval\", \"code\": \"{}\"}}", "1 ".repeat(1 << 20)));
+    assert!(replies[0].contains("Requests are limited to"), "{:?}", replies);
+    assert!(replies[1].contains("\"status\":\"done\""), "{:?}", replies);
+    // the connection goes on with the next line
+    let replies = client.request("{\"op\": \"eval\", \"code\": \"2\"}");
+    assert!(replies[0].contains("\"value\":\"Number(Int(2))\""), "{:?}", replies);
+}
+
+#[test]
+fn limits() {
+    let server = start();
+    let mut client = Client::connect(&server);
+    // the connection's own session is the first of eight
+    for _ in 0..7 {
+        assert!(client.request("{\"op\": \"clone\"}")[0].contains("new-session"));
+    }
+    let replies = client.request("{\"op\": \"clone\"}");
+    assert!(replies[0].contains("at most 8 sessions"), "{:?}", replies);
+
+    // a form that would run for ages is stopped, and the session goes on
+    client.request("{\"op\": \"eval\", \"code\": \"(define (spin n) (cond [(= n 0) 0] [else (spin (- n 1)) (spin (- n 1))]))\"}");
+    let replies = client.request("{\"op\": \"eval\", \"code\": \"(spin 100)\"}");
+    assert!(replies[0].contains("\"kind\":\"resource-exhausted\""), "{:?}", replies);
+    let replies = client.request("{\"op\": \"eval\", \"code\": \"(spin 1)\"}");
+    assert!(replies[0].contains("\"value\":\"Number(Int(0))\""), "{:?}", replies);
+}
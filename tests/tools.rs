@@ -0,0 +1,74 @@
+//! The `fmt` and `lint` subcommands, run on files in a temp dir.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+fn file(name: &str, source: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("rlisp-tools-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(name);
+    fs::write(&path, source).unwrap();
+    path
+}
+
+fn rlisp(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_rlisp")).args(args).output().expect("Failed to run rlisp")
+}
+
+#[test]
+fn fmt_check() {
+    let formatted = file("formatted.lisp", "(define (f x) (+ x 1))\n");
+    let messy = file("messy.lisp", "(define (f x)\n(+   x 1))");
+    let broken = file("broken.lisp", "(define (f x)");
+
+    let output = rlisp(&["fmt", "--check", formatted.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(output.stdout.is_empty() && output.stderr.is_empty());
+
+    let output = rlisp(&["fmt", "--check", formatted.to_str().unwrap(), messy.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("messy.lisp is not formatted") && !stderr.contains("formatted.lisp is"), "{}", stderr);
+    // checking changes nothing
+    assert_eq!(fs::read_to_string(&messy).unwrap(), "(define (f x)\n(+   x 1))");
+
+    let output = rlisp(&["fmt", "--check", broken.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8(output.stderr).unwrap().contains("broken.lisp:1:1:"));
+
+    assert_eq!(rlisp(&["fmt", "--write", messy.to_str().unwrap()]).status.code(), Some(0));
+    assert_eq!(fs::read_to_string(&messy).unwrap(), "(define (f x) (+ x 1))\n");
+    assert_eq!(rlisp(&["fmt", "--check", messy.to_str().unwrap()]).status.code(), Some(0));
+}
+
+#[test]
+fn fmt_keeps_comments() {
+    let commented = file("commented.lisp", "; top\n(define xs ; the list\n  '  ; quoted\n  (1 2))\n");
+    assert_eq!(rlisp(&["fmt", "--write", commented.to_str().unwrap()]).status.code(), Some(0));
+    let formatted = fs::read_to_string(&commented).unwrap();
+    for comment in ["; top", "; the list", "; quoted"].iter() {
+        assert!(formatted.contains(comment), "{} lost from {}", comment, formatted);
+    }
+}
+
+#[test]
+fn lint() {
+    let clean = file("clean.lisp", "(define (f x) (+ x 1))\n(f 2)\n");
+    let unbound = file("unbound.lisp", "(define (f x) (g x))\n");
+    let missing = env::temp_dir().join("rlisp-tools-missing.lisp");
+
+    assert_eq!(rlisp(&["lint", clean.to_str().unwrap()]).status.code(), Some(0));
+
+    let output = rlisp(&["lint", unbound.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("unbound.lisp:1:16: warning: g is not bound [unbound-symbol]"), "{}", stdout);
+
+    let output = rlisp(&["lint", missing.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8(output.stderr).unwrap().contains("Failed to read"));
+}